@@ -0,0 +1,56 @@
+use anyhow::Result;
+use raydium_amm_poc::{amm_math::PoolState, nonblocking::amm_math::load_state};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+// pool states are reloaded once they are older than ttl
+pub struct PoolCache {
+    pub client: RpcClient,
+    pub amm_program: Pubkey,
+    ttl: Duration,
+    /// most pools kept, the oldest is evicted first
+    capacity: usize,
+    pools: RwLock<HashMap<Pubkey, (Instant, PoolState)>>,
+}
+
+impl PoolCache {
+    pub fn new(client: RpcClient, amm_program: Pubkey, ttl: Duration, capacity: usize) -> Self {
+        PoolCache {
+            client,
+            amm_program,
+            ttl,
+            capacity,
+            pools: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get(&self, amm_pool: &Pubkey) -> Result<PoolState> {
+        if let Some((loaded_at, pool_state)) = self.pools.read().unwrap().get(amm_pool) {
+            if loaded_at.elapsed() < self.ttl {
+                return Ok(pool_state.clone());
+            }
+        }
+        // only ids that load as amm pools are cached
+        let pool_state = load_state(&self.client, &self.amm_program, amm_pool).await?;
+        let mut pools = self.pools.write().unwrap();
+        if pools.len() >= self.capacity && !pools.contains_key(amm_pool) {
+            pools.retain(|_, (loaded_at, _)| loaded_at.elapsed() < self.ttl);
+            if pools.len() >= self.capacity {
+                let oldest = pools
+                    .iter()
+                    .min_by_key(|(_, (loaded_at, _))| *loaded_at)
+                    .map(|(pool, _)| *pool);
+                if let Some(oldest) = oldest {
+                    pools.remove(&oldest);
+                }
+            }
+        }
+        pools.insert(*amm_pool, (Instant::now(), pool_state.clone()));
+        Ok(pool_state)
+    }
+}
@@ -0,0 +1,53 @@
+mod cache;
+mod routes;
+
+use anyhow::Result;
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use cache::PoolCache;
+use dotenv::dotenv;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::{env, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+
+const DEFAULT_SERVER_ADDR: &str = "127.0.0.1:8080";
+const DEFAULT_POOL_CACHE_TTL_MS: u64 = 2000;
+const DEFAULT_POOL_CACHE_SIZE: usize = 1024;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let rpc_url = env::var("RPC_URL").expect("RPC_URL must be set");
+    let addr: SocketAddr = env::var("SERVER_ADDR")
+        .unwrap_or_else(|_| DEFAULT_SERVER_ADDR.to_string())
+        .parse()?;
+    let ttl_ms: u64 = match env::var("POOL_CACHE_TTL_MS") {
+        Ok(ttl_ms) => ttl_ms.parse()?,
+        Err(_) => DEFAULT_POOL_CACHE_TTL_MS,
+    };
+    let cache_size: usize = match env::var("POOL_CACHE_SIZE") {
+        Ok(cache_size) => cache_size.parse()?,
+        Err(_) => DEFAULT_POOL_CACHE_SIZE,
+    };
+    let amm_program_key: Pubkey = Pubkey::from_str("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8")?;
+
+    let cache = Arc::new(PoolCache::new(
+        RpcClient::new(rpc_url),
+        amm_program_key,
+        Duration::from_millis(ttl_ms),
+        cache_size,
+    ));
+    let app = Router::new()
+        .route("/pools/:id", get(routes::get_pool))
+        .route("/quote", get(routes::get_quote))
+        .route("/swap-tx", post(routes::post_swap_tx))
+        .with_state(cache);
+
+    println!("===================Serving on {}===================", addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use raydium_amm_poc::{
+    amm_math::{calc_coin_in_pc, calc_pool_liquidity, quote_swap, PoolState, Quote},
+    nonblocking::priority_fee::estimate_swap_tx_config,
+    priority_fee::PriorityFeeConfig,
+    split::pool_swap_direction,
+    transaction::{build_unsigned_swap_transaction, serialize_transaction, SwapTxConfig},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_client::client_error::ClientError;
+use solana_sdk::pubkey::Pubkey;
+use std::{str::FromStr, sync::Arc};
+
+use crate::cache::PoolCache;
+
+const DEFAULT_SLIPPAGE_BPS: u64 = 50;
+
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: String) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message,
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: e.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+type ApiResult = Result<Json<Value>, ApiError>;
+
+// rpc failures are ours, anything else means the id is not a usable amm pool
+async fn load_pool(cache: &PoolCache, amm_pool: &Pubkey) -> Result<PoolState, ApiError> {
+    cache.get(amm_pool).await.map_err(|e| {
+        if e.downcast_ref::<ClientError>().is_some() {
+            ApiError::from(e)
+        } else {
+            ApiError::bad_request(e.to_string())
+        }
+    })
+}
+
+fn parse_pubkey(name: &str, value: &str) -> Result<Pubkey, ApiError> {
+    Pubkey::from_str(value).map_err(|e| ApiError::bad_request(format!("{}: {}", name, e)))
+}
+
+fn pool_json(pool_state: &PoolState) -> Value {
+    let keys = &pool_state.pool_amm_keys;
+    let pool = &pool_state.pool;
+    json!({
+        "id": keys.amm_pool.to_string(),
+        "coinMint": keys.amm_coin_mint.to_string(),
+        "pcMint": keys.amm_pc_mint.to_string(),
+        "lpMint": keys.amm_lp_mint.to_string(),
+        "coinVault": keys.amm_coin_vault.to_string(),
+        "pcVault": keys.amm_pc_vault.to_string(),
+        "openOrders": keys.amm_open_order.to_string(),
+        "marketProgram": keys.market_program.to_string(),
+        "market": keys.market.to_string(),
+        "coinVaultAmount": pool.pool_coin_vault_amount,
+        "pcVaultAmount": pool.pool_pc_vault_amount,
+        "coinDecimals": pool.pool_coin_decimals,
+        "pcDecimals": pool.pool_pc_decimals,
+        "lpAmount": pool.pool_lp_amount,
+        "swapFeeNumerator": pool.swap_fee_numerator,
+        "swapFeeDenominator": pool.swap_fee_denominator,
+        "coinPriceInPc": calc_coin_in_pc(pool).ok(),
+        "liquidityInPc": calc_pool_liquidity(pool).ok(),
+    })
+}
+
+fn quote_json(pool_state: &PoolState, input_mint: &Pubkey, quote: &Quote) -> Value {
+    let keys = &pool_state.pool_amm_keys;
+    let output_mint = if *input_mint == keys.amm_coin_mint {
+        keys.amm_pc_mint
+    } else {
+        keys.amm_coin_mint
+    };
+    json!({
+        "pool": keys.amm_pool.to_string(),
+        "inputMint": input_mint.to_string(),
+        "outputMint": output_mint.to_string(),
+        "swapDirection": format!("{:?}", quote.swap_direction),
+        "amountIn": quote.amount_in,
+        "amountOut": quote.amount_out,
+        "minAmountOut": quote.other_amount_threshold,
+        "feeAmount": quote.fee_amount,
+        "spotPriceBefore": quote.spot_price_before,
+        "spotPriceAfter": quote.spot_price_after,
+        "executionPrice": quote.execution_price,
+        "priceImpactBps": quote.price_impact_bps,
+    })
+}
+
+async fn load_quote(
+    cache: &PoolCache,
+    pool: &str,
+    input_mint: &str,
+    amount: u64,
+    slippage_bps: Option<u64>,
+) -> Result<(PoolState, Pubkey, Quote), ApiError> {
+    let amm_pool = parse_pubkey("pool", pool)?;
+    let input_mint = parse_pubkey("inputMint", input_mint)?;
+    let pool_state = load_pool(cache, &amm_pool).await?;
+    let swap_direction = pool_swap_direction(&pool_state, &input_mint)
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    let quote = quote_swap(
+        &pool_state.pool,
+        swap_direction,
+        amount,
+        true,
+        slippage_bps.unwrap_or(DEFAULT_SLIPPAGE_BPS),
+    )
+    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    Ok((pool_state, input_mint, quote))
+}
+
+pub async fn get_pool(State(cache): State<Arc<PoolCache>>, Path(id): Path<String>) -> ApiResult {
+    let amm_pool = parse_pubkey("id", &id)?;
+    let pool_state = load_pool(&cache, &amm_pool).await?;
+    Ok(Json(pool_json(&pool_state)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuoteParams {
+    pool: String,
+    input_mint: String,
+    amount: u64,
+    slippage_bps: Option<u64>,
+}
+
+pub async fn get_quote(
+    State(cache): State<Arc<PoolCache>>,
+    Query(params): Query<QuoteParams>,
+) -> ApiResult {
+    let (pool_state, input_mint, quote) = load_quote(
+        &cache,
+        &params.pool,
+        &params.input_mint,
+        params.amount,
+        params.slippage_bps,
+    )
+    .await?;
+    Ok(Json(quote_json(&pool_state, &input_mint, &quote)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SwapTxRequest {
+    pool: String,
+    input_mint: String,
+    amount: u64,
+    slippage_bps: Option<u64>,
+    /// micro lamports per compute unit, estimated from recent fees when unset
+    compute_unit_price: Option<u64>,
+    user: String,
+}
+
+// unsigned v0 transaction, the user signs it in their own wallet
+pub async fn post_swap_tx(
+    State(cache): State<Arc<PoolCache>>,
+    Json(request): Json<SwapTxRequest>,
+) -> ApiResult {
+    let user = parse_pubkey("user", &request.user)?;
+    let input_mint = parse_pubkey("inputMint", &request.input_mint)?;
+    let pool_state = load_pool(&cache, &parse_pubkey("pool", &request.pool)?).await?;
+    let slippage_bps = request.slippage_bps.unwrap_or(DEFAULT_SLIPPAGE_BPS);
+    // the limit comes from simulating the swap as the user, the price from the
+    // recent fees on the pool unless the request sets one
+    let estimated = estimate_swap_tx_config(
+        &cache.client,
+        &cache.amm_program,
+        &pool_state,
+        &user,
+        &input_mint,
+        request.amount,
+        slippage_bps,
+        &SwapTxConfig::default(),
+        &PriorityFeeConfig::default(),
+        &[],
+    )
+    .await
+    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    let config = SwapTxConfig {
+        compute_unit_price: request
+            .compute_unit_price
+            .unwrap_or(estimated.compute_unit_price),
+        ..estimated
+    };
+    let recent_blockhash = cache
+        .client
+        .get_latest_blockhash()
+        .await
+        .map_err(anyhow::Error::from)?;
+    let swap_tx = build_unsigned_swap_transaction(
+        &cache.amm_program,
+        &pool_state,
+        &user,
+        &input_mint,
+        request.amount,
+        slippage_bps,
+        &config,
+        &[],
+        recent_blockhash,
+    )
+    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    Ok(Json(json!({
+        "transaction": serialize_transaction(&swap_tx.transaction)?,
+        "quote": quote_json(&pool_state, &input_mint, &swap_tx.quote),
+    })))
+}
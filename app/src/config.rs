@@ -0,0 +1,32 @@
+use anyhow::{format_err, Result};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair},
+};
+use std::{env, str::FromStr};
+
+pub fn env_or<T: FromStr>(name: &str, default: T) -> Result<T> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| format_err!("{} has an invalid value {}", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+// comma separated, empty when unset
+pub fn env_pubkeys(name: &str) -> Result<Vec<Pubkey>> {
+    match env::var(name) {
+        Ok(keys) => keys
+            .split(',')
+            .filter(|key| !key.trim().is_empty())
+            .map(|key| Ok(Pubkey::from_str(key.trim())?))
+            .collect(),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+pub fn read_payer() -> Result<Keypair> {
+    let path = env::var("KEYPAIR_PATH").map_err(|_| format_err!("KEYPAIR_PATH must be set"))?;
+    read_keypair_file(&path).map_err(|e| format_err!("read keypair {}: {}", path, e))
+}
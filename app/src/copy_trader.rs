@@ -0,0 +1,386 @@
+use anyhow::{format_err, Result};
+use crossbeam_channel::{unbounded, Sender};
+use raydium_amm_poc::{
+    amm_math::load_state,
+    events::{decode_amm_transaction, parse_ray_logs, AmmEvent, AmmEventKind, RayLogEvent},
+    priority_fee::PriorityFeeConfig,
+    raydium_amm::maths::SwapDirection,
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+};
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::config::{env_or, env_pubkeys};
+use crate::execution::{
+    build_signed_swap_transaction, priority_fee_config_from_env, send_swap_transaction, SwapStatus,
+};
+use crate::indexer::PendingSignatures;
+use crate::status::{StatusBoard, TaskStatus};
+use crate::subscription::{logs_subscribe_with_reconnect, SubscriptionEvent};
+
+pub const COPY_TASK: &str = "copy";
+// how often the mirroring loop wakes up to check for shutdown
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+// signatures remembered to mirror a transaction once, far more than arrive
+// while the same transaction can still be reported twice
+const SEEN_SIGNATURES: usize = 4096;
+
+#[derive(Clone, Debug)]
+pub struct CopyConfig {
+    pub wallets: Vec<Pubkey>,
+    /// our input amount relative to the watched wallet's
+    pub scale: f64,
+    /// raw units of the input mint, larger mirrors are capped
+    pub max_amount_in: u64,
+    /// a swap is mirrored when either pool mint is listed, empty mirrors every swap
+    pub allowed_mints: Vec<Pubkey>,
+    pub slippage_bps: u64,
+    pub priority_fee: PriorityFeeConfig,
+}
+
+impl CopyConfig {
+    pub fn from_env() -> Result<Self> {
+        Ok(CopyConfig {
+            wallets: env_pubkeys("COPY_WALLETS")?,
+            scale: env_or("COPY_SCALE", 1.0)?,
+            max_amount_in: env_or("COPY_MAX_AMOUNT_IN", u64::MAX)?,
+            allowed_mints: env_pubkeys("COPY_MINTS")?,
+            slippage_bps: env_or("COPY_SLIPPAGE_BPS", 100)?,
+            priority_fee: priority_fee_config_from_env()?,
+        })
+    }
+}
+
+// a swap of a watched wallet, decoded from its ray_log and instruction
+#[derive(Clone, Debug)]
+pub struct WalletSwap {
+    pub wallet: Pubkey,
+    pub signature: String,
+    pub pool: Pubkey,
+    pub swap_direction: SwapDirection,
+    /// what the wallet paid, raw units of the input side
+    pub amount_in: u64,
+}
+
+impl WalletSwap {
+    fn from_event(wallet: &Pubkey, event: &AmmEvent) -> Option<Self> {
+        let swap_direction = event.swap_direction?;
+        let amount_in = match swap_direction {
+            SwapDirection::Coin2PC => event.coin_amount,
+            SwapDirection::PC2Coin => event.pc_amount,
+        };
+        Some(WalletSwap {
+            wallet: *wallet,
+            signature: event.signature.clone(),
+            pool: event.pool,
+            swap_direction,
+            amount_in,
+        })
+    }
+}
+
+// swaps in the transaction, only when the wallet signed it
+fn wallet_swaps(
+    amm_program_key: &Pubkey,
+    wallet: &Pubkey,
+    transaction: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<Vec<WalletSwap>> {
+    let versioned = transaction
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| format_err!("transaction is not binary encoded"))?;
+    let signers = versioned.message.header().num_required_signatures as usize;
+    if !versioned.message.static_account_keys()[..signers].contains(wallet) {
+        return Ok(Vec::new());
+    }
+    Ok(decode_amm_transaction(amm_program_key, transaction)?
+        .iter()
+        .filter(|event| event.kind == AmmEventKind::Swap)
+        .filter_map(|event| WalletSwap::from_event(wallet, event))
+        .collect())
+}
+
+fn mirror_swap(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    config: &CopyConfig,
+    payer: &Keypair,
+    swap: &WalletSwap,
+) -> Result<()> {
+    let pool = load_state(client, amm_program_key, &swap.pool)?;
+    let keys = &pool.pool_amm_keys;
+    if !config.allowed_mints.is_empty()
+        && !config.allowed_mints.contains(&keys.amm_coin_mint)
+        && !config.allowed_mints.contains(&keys.amm_pc_mint)
+    {
+        return Err(format_err!(
+            "pool {} mints {} and {} are not allowed",
+            swap.pool,
+            keys.amm_coin_mint,
+            keys.amm_pc_mint
+        ));
+    }
+    let input_mint = match swap.swap_direction {
+        SwapDirection::Coin2PC => keys.amm_coin_mint,
+        SwapDirection::PC2Coin => keys.amm_pc_mint,
+    };
+    let amount_in = ((swap.amount_in as f64 * config.scale) as u64).min(config.max_amount_in);
+    if amount_in == 0 {
+        return Err(format_err!("scaled amount is zero"));
+    }
+    // our own quote and slippage, sol inputs are wrapped by the swap builder
+    let (transaction, min_amount_out) = build_signed_swap_transaction(
+        client,
+        amm_program_key,
+        &pool,
+        payer,
+        &input_mint,
+        amount_in,
+        config.slippage_bps,
+        &config.priority_fee,
+    )?;
+    let signature = transaction.signatures[0];
+    match send_swap_transaction(client, &transaction)? {
+        SwapStatus::Landed => println!(
+            "copied {} {:?} {} of {} for at least {} by {}",
+            swap.wallet, swap.swap_direction, amount_in, input_mint, min_amount_out, signature
+        ),
+        SwapStatus::Pending => println!("copy {} sent, not confirmed yet", signature),
+        SwapStatus::Failed => return Err(format_err!("copy {} did not land", signature)),
+    }
+    Ok(())
+}
+
+// the most recent signatures, the oldest is forgotten first
+struct SeenSignatures {
+    order: VecDeque<String>,
+    signatures: HashSet<String>,
+}
+
+impl SeenSignatures {
+    fn new() -> Self {
+        SeenSignatures {
+            order: VecDeque::with_capacity(SEEN_SIGNATURES),
+            signatures: HashSet::with_capacity(SEEN_SIGNATURES),
+        }
+    }
+
+    // false if the signature was already seen
+    fn insert(&mut self, signature: &str) -> bool {
+        if self.signatures.contains(signature) {
+            return false;
+        }
+        if self.order.len() == SEEN_SIGNATURES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.signatures.remove(&oldest);
+            }
+        }
+        self.order.push_back(signature.to_string());
+        self.signatures.insert(signature.to_string());
+        true
+    }
+}
+
+// one logs subscription per wallet, mentions filters take a single address
+fn watch_wallet(
+    url: &str,
+    wallet: Pubkey,
+    sender: Sender<(Pubkey, String, u64)>,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    logs_subscribe_with_reconnect(
+        url,
+        vec![wallet.to_string()],
+        CommitmentConfig::confirmed(),
+        shutdown,
+        |event| match event {
+            SubscriptionEvent::Message(log_response) => {
+                status.record_event(COPY_TASK, log_response.context.slot);
+                let value = log_response.value;
+                let swapped = value.err.is_none()
+                    && parse_ray_logs(&value.logs).iter().any(|log| {
+                        matches!(
+                            log,
+                            RayLogEvent::SwapBaseIn(_) | RayLogEvent::SwapBaseOut(_)
+                        )
+                    });
+                if swapped {
+                    let _ = sender.send((wallet, value.signature, log_response.context.slot));
+                }
+            }
+            SubscriptionEvent::Gap(gap) => {
+                status.record_gap(COPY_TASK);
+                println!(
+                    "copy gap for {}: slots {}..{} may have been missed",
+                    wallet,
+                    gap.last_seen + 1,
+                    gap.resumed_at
+                );
+            }
+            SubscriptionEvent::Disconnected => {
+                status.set_status(COPY_TASK, TaskStatus::Reconnecting);
+            }
+        },
+    )
+}
+
+// mirrors every swap the watched wallets sign until shutdown
+pub fn run_copy_trader(
+    url: &str,
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    config: &CopyConfig,
+    payer: &Keypair,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    if config.wallets.is_empty() {
+        return Err(format_err!("COPY_WALLETS must be set"));
+    }
+    let (sender, receiver) = unbounded::<(Pubkey, String, u64)>();
+    thread::scope(|scope| {
+        let watchers: Vec<_> = config
+            .wallets
+            .iter()
+            .map(|wallet| {
+                let sender = sender.clone();
+                scope.spawn(move || watch_wallet(url, *wallet, sender, status, shutdown))
+            })
+            .collect();
+        drop(sender);
+
+        // a transaction mentioning two watched wallets is mirrored once
+        let mut seen = SeenSignatures::new();
+        // transactions not fetched yet, with the wallet that reported them
+        let mut pending = PendingSignatures::new();
+        let mut pending_wallets: HashMap<Signature, Pubkey> = HashMap::new();
+        while !shutdown.load(Ordering::Relaxed) {
+            match receiver.recv_timeout(RECEIVE_TIMEOUT) {
+                Ok((wallet, signature, slot)) => {
+                    if seen.insert(&signature) {
+                        match signature.parse::<Signature>() {
+                            Ok(signature) => {
+                                pending.push(signature, slot);
+                                pending_wallets.insert(signature, wallet);
+                            }
+                            Err(e) => println!("bad signature {}: {}", signature, e),
+                        }
+                    }
+                }
+                Err(e) if e.is_timeout() => {}
+                Err(_) => break,
+            }
+            // worked on every wake up, so retries go on while no swaps arrive
+            pending.fetch_due(client, |signature, _slot, transaction| {
+                let wallet = match pending_wallets.remove(signature) {
+                    Some(wallet) => wallet,
+                    None => return,
+                };
+                let swaps = match transaction
+                    .and_then(|transaction| wallet_swaps(amm_program_key, &wallet, &transaction))
+                {
+                    Ok(swaps) => swaps,
+                    Err(e) => {
+                        println!("copy skipped {}: {}", signature, e);
+                        return;
+                    }
+                };
+                for swap in swaps.iter() {
+                    if let Err(e) = mirror_swap(client, amm_program_key, config, payer, swap) {
+                        println!("copy of {} skipped: {}", swap.signature, e);
+                    }
+                }
+            });
+        }
+        let unfetched = pending.drain();
+        if !unfetched.is_empty() {
+            println!("{} swaps not copied before shutdown", unfetched.len());
+        }
+
+        for watcher in watchers {
+            match watcher.join() {
+                Ok(Err(e)) => return Err(e),
+                Err(_) => return Err(format_err!("wallet watcher panicked")),
+                Ok(Ok(())) => {}
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(swap_direction: Option<SwapDirection>) -> AmmEvent {
+        AmmEvent {
+            signature: "sig".to_string(),
+            index: 0,
+            slot: 10,
+            block_time: None,
+            pool: Pubkey::new_unique(),
+            kind: AmmEventKind::Swap,
+            swap_direction,
+            coin_amount: 300,
+            pc_amount: 7_000,
+            decimals: None,
+            open_time: None,
+        }
+    }
+
+    #[test]
+    fn wallet_swap_pays_the_input_side() {
+        let wallet = Pubkey::new_unique();
+        let sell = event(Some(SwapDirection::Coin2PC));
+        let swap = WalletSwap::from_event(&wallet, &sell).unwrap();
+        assert_eq!(swap.wallet, wallet);
+        assert_eq!(swap.pool, sell.pool);
+        assert_eq!(swap.signature, "sig");
+        assert!(matches!(swap.swap_direction, SwapDirection::Coin2PC));
+        assert_eq!(swap.amount_in, 300);
+
+        let buy = WalletSwap::from_event(&wallet, &event(Some(SwapDirection::PC2Coin))).unwrap();
+        assert!(matches!(buy.swap_direction, SwapDirection::PC2Coin));
+        assert_eq!(buy.amount_in, 7_000);
+    }
+
+    #[test]
+    fn wallet_swap_needs_a_direction() {
+        assert!(WalletSwap::from_event(&Pubkey::new_unique(), &event(None)).is_none());
+    }
+
+    #[test]
+    fn seen_signatures_reports_repeats() {
+        let mut seen = SeenSignatures::new();
+        assert!(seen.insert("a"));
+        assert!(seen.insert("b"));
+        assert!(!seen.insert("a"));
+        assert!(!seen.insert("b"));
+    }
+
+    #[test]
+    fn seen_signatures_forgets_the_oldest() {
+        let mut seen = SeenSignatures::new();
+        for i in 0..SEEN_SIGNATURES {
+            assert!(seen.insert(&i.to_string()));
+        }
+        assert!(!seen.insert("0"));
+        // one past the capacity evicts the first signature only
+        assert!(seen.insert("new"));
+        assert_eq!(seen.order.len(), SEEN_SIGNATURES);
+        assert!(seen.insert("0"));
+        assert!(!seen.insert("2"));
+    }
+}
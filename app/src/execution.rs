@@ -0,0 +1,191 @@
+use anyhow::{format_err, Result};
+use raydium_amm_poc::{
+    amm_math::PoolState,
+    priority_fee::{
+        estimate_swap_tx_config_with_min_out, quoted_min_amount_out, PriorityFeeConfig,
+    },
+    rpc::simulate_transaction,
+    transaction::{build_swap_instructions, build_swap_instructions_with_min_out, SwapTxConfig},
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::Instruction,
+    message::Message,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+
+use crate::config::env_or;
+
+// priority fee settings for every swap the bot sends
+pub fn priority_fee_config_from_env() -> Result<PriorityFeeConfig> {
+    let default = PriorityFeeConfig::default();
+    Ok(PriorityFeeConfig {
+        percentile: env_or("PRIORITY_FEE_PERCENTILE", default.percentile)?,
+        compute_unit_margin_bps: env_or(
+            "COMPUTE_UNIT_MARGIN_BPS",
+            default.compute_unit_margin_bps,
+        )?,
+        max_compute_unit_price: match env_or("MAX_COMPUTE_UNIT_PRICE", 0)? {
+            0 => default.max_compute_unit_price,
+            max_compute_unit_price => Some(max_compute_unit_price),
+        },
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwapStatus {
+    Landed,
+    /// failed on chain, or expired before it was processed, safe to send again
+    Failed,
+    /// not confirmed yet and still able to land
+    Pending,
+}
+
+// where a sent swap stands. the blockhash is read first, a signature the
+// cluster has never seen once its blockhash expired can not land anymore
+pub fn swap_status(
+    client: &RpcClient,
+    signature: &Signature,
+    recent_blockhash: &Hash,
+) -> Result<SwapStatus> {
+    let expired = !client.is_blockhash_valid(recent_blockhash, CommitmentConfig::processed())?;
+    let status = |commitment: CommitmentConfig| {
+        client.get_signature_status_with_commitment_and_history(signature, commitment, true)
+    };
+    Ok(match status(CommitmentConfig::confirmed())? {
+        Some(Ok(())) => SwapStatus::Landed,
+        Some(Err(_)) => SwapStatus::Failed,
+        None => match status(CommitmentConfig::processed())? {
+            Some(_) => SwapStatus::Pending,
+            None if expired => SwapStatus::Failed,
+            None => SwapStatus::Pending,
+        },
+    })
+}
+
+// a send or confirmation error does not tell whether the swap landed, the
+// outcome is read back by signature
+pub fn send_swap_transaction(client: &RpcClient, transaction: &Transaction) -> Result<SwapStatus> {
+    match client.send_and_confirm_transaction(transaction) {
+        Ok(_) => Ok(SwapStatus::Landed),
+        Err(e) => {
+            println!("swap {} not confirmed: {}", transaction.signatures[0], e);
+            swap_status(
+                client,
+                &transaction.signatures[0],
+                &transaction.message.recent_blockhash,
+            )
+        }
+    }
+}
+
+fn sign(client: &RpcClient, payer: &Keypair, instructions: &[Instruction]) -> Result<Transaction> {
+    Ok(Transaction::new_signed_with_payer(
+        instructions,
+        Some(&payer.pubkey()),
+        &[payer],
+        client.get_latest_blockhash()?,
+    ))
+}
+
+// swap base in of amount_in with the min out from the quote on the given state
+pub fn build_signed_swap_transaction(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    pool: &PoolState,
+    payer: &Keypair,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    slippage_bps: u64,
+    fee_config: &PriorityFeeConfig,
+) -> Result<(Transaction, u64)> {
+    let min_amount_out = quoted_min_amount_out(pool, input_mint, amount_in, slippage_bps)?;
+    let transaction = build_signed_swap_transaction_with_min_out(
+        client,
+        amm_program_key,
+        pool,
+        payer,
+        input_mint,
+        amount_in,
+        min_amount_out,
+        fee_config,
+    )?;
+    Ok((transaction, min_amount_out))
+}
+
+// swap base in of amount_in that fails below min_amount_out. it is simulated
+// first for its compute unit limit and priced from the recent fees on the pool
+pub fn build_signed_swap_transaction_with_min_out(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    pool: &PoolState,
+    payer: &Keypair,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    min_amount_out: u64,
+    fee_config: &PriorityFeeConfig,
+) -> Result<Transaction> {
+    let config = estimate_swap_tx_config_with_min_out(
+        client,
+        amm_program_key,
+        pool,
+        &payer.pubkey(),
+        input_mint,
+        amount_in,
+        min_amount_out,
+        &SwapTxConfig::default(),
+        fee_config,
+        &[],
+    )?;
+    let instructions = build_swap_instructions_with_min_out(
+        amm_program_key,
+        pool,
+        &payer.pubkey(),
+        input_mint,
+        amount_in,
+        min_amount_out,
+        &config,
+    )?;
+    sign(client, payer, &instructions)
+}
+
+// runs the swap unsigned against the current bank, errors if it would fail
+pub fn simulate_swap(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    pool: &PoolState,
+    owner: &Pubkey,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    slippage_bps: u64,
+) -> Result<u64> {
+    let (instructions, quote) = build_swap_instructions(
+        amm_program_key,
+        pool,
+        owner,
+        input_mint,
+        amount_in,
+        slippage_bps,
+        &SwapTxConfig::default(),
+    )?;
+    let mut message = Message::new(&instructions, Some(owner));
+    message.recent_blockhash = client.get_latest_blockhash()?;
+    let result = simulate_transaction(
+        client,
+        &Transaction::new_unsigned(message),
+        false,
+        CommitmentConfig::confirmed(),
+    )?;
+    if let Some(err) = result.value.err {
+        return Err(format_err!(
+            "simulation failed: {} {:?}",
+            err,
+            result.value.logs.unwrap_or_default()
+        ));
+    }
+    Ok(quote.other_amount_threshold)
+}
@@ -0,0 +1,445 @@
+use anyhow::{format_err, Result};
+use raydium_amm_poc::{
+    events::{decode_amm_transaction, AmmEvent, AmmEventKind},
+    raydium_amm::state::AmmInfo,
+    rpc::get_account,
+};
+use solana_client::{
+    rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
+    rpc_config::RpcTransactionConfig,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::status::{StatusBoard, TaskStatus};
+use crate::store::EventStore;
+use crate::subscription::{logs_subscribe_with_reconnect, wait_unless_shutdown, SubscriptionEvent};
+
+pub const INDEXER_TASK: &str = "indexer";
+pub const BACKFILL_TASK: &str = "backfill";
+const SIGNATURES_PAGE_LIMIT: usize = 1000;
+
+// (coin, pc) decimals per pool, needed to turn event amounts into a price
+#[derive(Default)]
+pub struct DecimalsCache {
+    decimals: HashMap<Pubkey, (u64, u64)>,
+}
+
+impl DecimalsCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self, client: &RpcClient, event: &AmmEvent) -> Option<(u64, u64)> {
+        if let Some(decimals) = event.decimals {
+            self.decimals.insert(event.pool, decimals);
+            return Some(decimals);
+        }
+        if let Some(decimals) = self.decimals.get(&event.pool) {
+            return Some(*decimals);
+        }
+        let amm = get_account::<AmmInfo>(client, &event.pool).ok()??;
+        let decimals = (amm.coin_decimals, amm.pc_decimals);
+        self.decimals.insert(event.pool, decimals);
+        Some(decimals)
+    }
+}
+
+// binary encoded, as decode_amm_transaction needs
+pub fn fetch_transaction(
+    client: &RpcClient,
+    signature: &Signature,
+) -> Result<EncodedConfirmedTransactionWithStatusMeta> {
+    Ok(client.get_transaction_with_config(
+        signature,
+        RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            commitment: Some(CommitmentConfig::confirmed()),
+            max_supported_transaction_version: Some(0),
+        },
+    )?)
+}
+
+// rate limits, timeouts and transactions the node has not made available yet
+// all fail getTransaction, so a failed fetch is retried before giving up
+const FETCH_MAX_ATTEMPTS: u32 = 8;
+const FETCH_INITIAL_RETRY: Duration = Duration::from_millis(500);
+const FETCH_MAX_RETRY: Duration = Duration::from_secs(30);
+
+fn fetch_retry_delay(failed_attempts: u32) -> Duration {
+    FETCH_INITIAL_RETRY
+        .checked_mul(1 << failed_attempts.saturating_sub(1).min(16))
+        .map_or(FETCH_MAX_RETRY, |delay| delay.min(FETCH_MAX_RETRY))
+}
+
+// Ok(None) if shutdown came first, the last error once the attempts run out
+pub fn fetch_transaction_with_retry(
+    client: &RpcClient,
+    signature: &Signature,
+    shutdown: &AtomicBool,
+) -> Result<Option<EncodedConfirmedTransactionWithStatusMeta>> {
+    let mut failed_attempts = 0;
+    loop {
+        match fetch_transaction(client, signature) {
+            Ok(transaction) => return Ok(Some(transaction)),
+            Err(e) => {
+                failed_attempts += 1;
+                if failed_attempts >= FETCH_MAX_ATTEMPTS {
+                    return Err(e);
+                }
+                let wait = fetch_retry_delay(failed_attempts);
+                println!("fetch {} failed: {}, retrying in {:?}", signature, e, wait);
+                if !wait_unless_shutdown(wait, shutdown) {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+struct PendingSignature {
+    signature: Signature,
+    slot: u64,
+    failed_attempts: u32,
+    retry_at: Instant,
+}
+
+// signatures from log notifications waiting for their transaction. at
+// confirmed commitment getTransaction often does not have it yet, so a failed
+// fetch is tried again later instead of losing the event
+#[derive(Default)]
+pub struct PendingSignatures {
+    pending: VecDeque<PendingSignature>,
+}
+
+impl PendingSignatures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, signature: Signature, slot: u64) {
+        self.pending.push_back(PendingSignature {
+            signature,
+            slot,
+            failed_attempts: 0,
+            retry_at: Instant::now(),
+        });
+    }
+
+    // fetches every signature that is due. on_fetched gets the transaction, or
+    // the last error once the attempts ran out, the others wait for a retry
+    pub fn fetch_due<F>(&mut self, client: &RpcClient, mut on_fetched: F)
+    where
+        F: FnMut(&Signature, u64, Result<EncodedConfirmedTransactionWithStatusMeta>),
+    {
+        let now = Instant::now();
+        let mut waiting = VecDeque::with_capacity(self.pending.len());
+        while let Some(mut pending) = self.pending.pop_front() {
+            if pending.retry_at > now {
+                waiting.push_back(pending);
+                continue;
+            }
+            match fetch_transaction(client, &pending.signature) {
+                Ok(transaction) => on_fetched(&pending.signature, pending.slot, Ok(transaction)),
+                Err(e) => {
+                    pending.failed_attempts += 1;
+                    if pending.failed_attempts >= FETCH_MAX_ATTEMPTS {
+                        on_fetched(&pending.signature, pending.slot, Err(e));
+                    } else {
+                        pending.retry_at = now + fetch_retry_delay(pending.failed_attempts);
+                        waiting.push_back(pending);
+                    }
+                }
+            }
+        }
+        self.pending = waiting;
+    }
+
+    // the signatures still waiting, e.g. at shutdown
+    pub fn drain(&mut self) -> Vec<(Signature, u64)> {
+        self.pending
+            .drain(..)
+            .map(|pending| (pending.signature, pending.slot))
+            .collect()
+    }
+}
+
+pub fn fetch_amm_events(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    signature: &Signature,
+) -> Result<Vec<AmmEvent>> {
+    decode_amm_transaction(amm_program_key, &fetch_transaction(client, signature)?)
+}
+
+// the pool an initialize transaction created
+pub fn fetch_init_pool(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    signature: &Signature,
+) -> Result<Pubkey> {
+    fetch_amm_events(client, amm_program_key, signature)?
+        .iter()
+        .find(|event| event.kind == AmmEventKind::Init)
+        .map(|event| event.pool)
+        .ok_or_else(|| format_err!("no init instruction in {}", signature))
+}
+
+// returns how many events were new to the store
+pub fn store_events(
+    client: &RpcClient,
+    store: &mut EventStore,
+    decimals: &mut DecimalsCache,
+    events: &[AmmEvent],
+) -> Result<usize> {
+    let mut inserted = 0;
+    for event in events.iter() {
+        let price = decimals
+            .get(client, event)
+            .and_then(|(coin_decimals, pc_decimals)| event.price(coin_decimals, pc_decimals));
+        if store.insert_event(event, price)? {
+            inserted += 1;
+        }
+    }
+    Ok(inserted)
+}
+
+// writes every amm event seen in the program logs until shutdown. a
+// transaction that can not be fetched or decoded is listed in the store's
+// skipped transactions instead
+pub fn run_indexer(
+    url: &str,
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    store: &mut EventStore,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    let mut decimals = DecimalsCache::new();
+    let mut pending = PendingSignatures::new();
+    let result = logs_subscribe_with_reconnect(
+        url,
+        vec![amm_program_key.to_string()],
+        CommitmentConfig::confirmed(),
+        shutdown,
+        |event| {
+            match event {
+                SubscriptionEvent::Message(log_response) => {
+                    let value = log_response.value;
+                    if value.err.is_none() && value.logs.iter().any(|log| log.contains("ray_log:"))
+                    {
+                        match Signature::from_str(&value.signature) {
+                            Ok(signature) => pending.push(signature, log_response.context.slot),
+                            Err(e) => println!("bad signature {}: {}", value.signature, e),
+                        }
+                    }
+                }
+                SubscriptionEvent::Gap(gap) => {
+                    status.record_gap(INDEXER_TASK);
+                    println!(
+                        "indexer gap: slots {}..{} may have been missed",
+                        gap.last_seen + 1,
+                        gap.resumed_at
+                    );
+                }
+                SubscriptionEvent::Disconnected => {
+                    status.set_status(INDEXER_TASK, TaskStatus::Reconnecting);
+                }
+            }
+            // worked on every event, so retries go on as long as logs arrive
+            pending.fetch_due(client, |signature, slot, transaction| {
+                let result = transaction
+                    .and_then(|transaction| decode_amm_transaction(amm_program_key, &transaction))
+                    .and_then(|events| store_events(client, store, &mut decimals, &events));
+                match result {
+                    Ok(_) => status.record_event(INDEXER_TASK, slot),
+                    Err(e) => {
+                        println!("failed to index {}: {}", signature, e);
+                        let skipped = store.record_skipped_transaction(
+                            &signature.to_string(),
+                            None,
+                            slot,
+                            &e.to_string(),
+                        );
+                        if let Err(e) = skipped {
+                            println!("failed to record {}: {}", signature, e);
+                        }
+                    }
+                }
+            });
+        },
+    );
+    let unfetched = pending.drain();
+    if !unfetched.is_empty() {
+        println!("{} signatures not indexed before shutdown", unfetched.len());
+    }
+    for (signature, slot) in unfetched {
+        store.record_skipped_transaction(
+            &signature.to_string(),
+            None,
+            slot,
+            "not fetched before shutdown",
+        )?;
+    }
+    result
+}
+
+// pages newest first from below before down to above until, calling processed
+// for every signature handled. false if shutdown stopped it first
+fn backfill_range<F>(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    amm_pool: &Pubkey,
+    store: &mut EventStore,
+    decimals: &mut DecimalsCache,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
+    mut before: Option<Signature>,
+    until: Option<Signature>,
+    inserted: &mut usize,
+    mut processed: F,
+) -> Result<bool>
+where
+    F: FnMut(&EventStore, &str) -> Result<()>,
+{
+    while !shutdown.load(Ordering::Relaxed) {
+        let page = client.get_signatures_for_address_with_config(
+            amm_pool,
+            GetConfirmedSignaturesForAddress2Config {
+                before,
+                until,
+                limit: Some(SIGNATURES_PAGE_LIMIT),
+                commitment: Some(CommitmentConfig::confirmed()),
+            },
+        )?;
+        if page.is_empty() {
+            return Ok(true);
+        }
+        for signature_status in page.iter() {
+            if shutdown.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+            let signature = Signature::from_str(&signature_status.signature)?;
+            if signature_status.err.is_none()
+                && !store.contains_signature(&signature_status.signature)?
+            {
+                // a fetch that keeps failing stops the backfill before the
+                // cursor moves past it, the next run starts over from there
+                let transaction = match fetch_transaction_with_retry(client, &signature, shutdown) {
+                    Ok(Some(transaction)) => transaction,
+                    Ok(None) => return Ok(false),
+                    Err(e) => {
+                        return Err(format_err!(
+                            "backfill {} stopped at {}: {}",
+                            amm_pool,
+                            signature,
+                            e
+                        ))
+                    }
+                };
+                // one undecodable transaction does not stop the backfill
+                match decode_amm_transaction(amm_program_key, &transaction) {
+                    Ok(events) => *inserted += store_events(client, store, decimals, &events)?,
+                    Err(e) => {
+                        println!("backfill {} skipped {}: {}", amm_pool, signature, e);
+                        store.record_skipped_transaction(
+                            &signature_status.signature,
+                            Some(&amm_pool.to_string()),
+                            signature_status.slot,
+                            &e.to_string(),
+                        )?;
+                    }
+                }
+            }
+            processed(store, &signature_status.signature)?;
+            status.record_event(BACKFILL_TASK, signature_status.slot);
+            before = Some(signature);
+        }
+        println!(
+            "backfill {}: {} signatures down to slot {}, {} new events",
+            amm_pool,
+            page.len(),
+            page.last().map(|s| s.slot).unwrap_or_default(),
+            inserted
+        );
+    }
+    Ok(false)
+}
+
+// first the signatures that arrived since the last run, then older history
+// from below the oldest processed one back to the first. returns how many
+// events were new
+pub fn backfill_pool(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    amm_pool: &Pubkey,
+    store: &mut EventStore,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
+) -> Result<usize> {
+    let pool = amm_pool.to_string();
+    let mut decimals = DecimalsCache::new();
+    let cursor = store.backfill_cursor(&pool)?;
+    let mut inserted = 0;
+    status.set_status(BACKFILL_TASK, TaskStatus::Running);
+
+    if let Some(newest) = &cursor.newest {
+        // newest only moves up once the gap below the new top is closed
+        let mut top: Option<String> = None;
+        let complete = backfill_range(
+            client,
+            amm_program_key,
+            amm_pool,
+            store,
+            &mut decimals,
+            status,
+            shutdown,
+            None,
+            Some(Signature::from_str(newest)?),
+            &mut inserted,
+            |_, signature| {
+                top.get_or_insert_with(|| signature.to_string());
+                Ok(())
+            },
+        )?;
+        if !complete {
+            return Ok(inserted);
+        }
+        if let Some(top) = top {
+            store.set_backfill_newest(&pool, &top)?;
+        }
+    }
+
+    let before = match &cursor.oldest {
+        Some(oldest) => Some(Signature::from_str(oldest)?),
+        None => None,
+    };
+    // a first run starts at the tip, which becomes its newest
+    let mut newest_set = cursor.newest.is_some();
+    backfill_range(
+        client,
+        amm_program_key,
+        amm_pool,
+        store,
+        &mut decimals,
+        status,
+        shutdown,
+        before,
+        None,
+        &mut inserted,
+        |store, signature| {
+            if !newest_set {
+                store.set_backfill_newest(&pool, signature)?;
+                newest_set = true;
+            }
+            store.set_backfill_oldest(&pool, signature)
+        },
+    )?;
+    Ok(inserted)
+}
@@ -1,5 +1,18 @@
-use anyhow::Result;
-// use backoff;
+mod config;
+mod copy_trader;
+mod execution;
+mod indexer;
+mod orders;
+mod portfolio;
+mod positions;
+mod registry;
+mod sniper;
+mod status;
+mod store;
+mod subscription;
+mod twap;
+
+use anyhow::{format_err, Result};
 use dotenv::dotenv;
 use raydium_amm::{
     log::{InitLog, LogType},
@@ -11,15 +24,36 @@ use raydium_amm_poc::amm_math::{
     calc_coin_in_pc, calc_coin_market_cap, load_state, simulate_calc_swap_token_amount,
     swap_with_slippage, PoolState,
 };
+use orders::{OrderBook, OrderSide};
+use positions::{position_price, PositionBook};
+use raydium_amm_poc::nonblocking::amm_math::load_states;
+use raydium_amm_poc::pool_discovery::{find_pools_for_mint, find_pools_for_pair};
 use raydium_amm_poc::raydium_amm::maths::SwapDirection;
+use raydium_amm_poc::token::fetch_pool_mint_infos;
+use registry::PoolRegistry;
 use solana_client::{
-    pubsub_client::PubsubClient,
-    rpc_client::RpcClient,
-    rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter},
+    nonblocking::rpc_client::RpcClient as NonblockingRpcClient, rpc_client::RpcClient,
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
 };
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use status::{StatusBoard, TaskStatus};
+use store::EventStore;
 use std::env;
 use std::str::FromStr;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use subscription::{
+    logs_subscribe_with_reconnect, slot_subscribe_with_reconnect, SubscriptionEvent,
+};
 // use std::{collections::HashMap, env, str::FromStr, time::Duration};
 // use yellowstone_grpc_client::{GeyserGrpcClient, GeyserGrpcClientError};
 // use yellowstone_grpc_proto::prelude::{
@@ -35,6 +69,15 @@ use std::str::FromStr;
 // type BlocksFilterMap = HashMap<String, SubscribeRequestFilterBlocks>;
 // type BlocksMetaFilterMap = HashMap<String, SubscribeRequestFilterBlocksMeta>;
 
+const NEW_POOLS_TASK: &str = "new-pools";
+const NEW_BLOCKS_TASK: &str = "new-blocks";
+const STATUS_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+// registry pools are reloaded every this many slots
+const BLOCK_REFRESH_SLOTS: u64 = 4;
+const DEFAULT_INDEX_DB: &str = "raydium_index.sqlite";
+// SOL/USDT, the usd reference for sol
+const SOL_USD_POOL: &str = "7XawhbbxtsRcQA8KTkHT9f9nc6d69UwqCDh6U5EEbEmX";
+
 #[derive(Debug)]
 pub enum SimulationMode {
     MainNetMode,
@@ -50,14 +93,9 @@ fn simulate_swap_tx(
     in_amount_specific: u64,
     out_amount_specific: u64,
 ) -> Result<()> {
-    let user_source = spl_associated_token_account::get_associated_token_address(
-        &wallet_pubkey,
-        &state_coin_pc.pool_amm_keys.amm_coin_mint,
-    );
-    let user_destination = spl_associated_token_account::get_associated_token_address(
-        &wallet_pubkey,
-        &state_coin_pc.pool_amm_keys.amm_pc_mint,
-    );
+    let (coin_mint_info, pc_mint_info) = fetch_pool_mint_infos(&client, &state_coin_pc)?;
+    let user_source = coin_mint_info.associated_token_address(&wallet_pubkey);
+    let user_destination = pc_mint_info.associated_token_address(&wallet_pubkey);
     let amount_threshold_for_base_out = swap_with_slippage(
         state_coin_pc.pool.pool_pc_vault_amount,
         state_coin_pc.pool.pool_coin_vault_amount,
@@ -175,19 +213,19 @@ fn fetch_pool_info(
     amm_program_key: &Pubkey,
     coin_pc_pool: &Pubkey,
 ) -> Result<PoolState> {
-    let sol_usdt_pool: Pubkey = Pubkey::from_str("7XawhbbxtsRcQA8KTkHT9f9nc6d69UwqCDh6U5EEbEmX")?;
-    let state_sol_usd = load_state(&client, &amm_program_key, &sol_usdt_pool).unwrap();
+    let sol_usdt_pool: Pubkey = Pubkey::from_str(SOL_USD_POOL)?;
+    let state_sol_usd = load_state(&client, &amm_program_key, &sol_usdt_pool)?;
 
-    let state_coin_pc = load_state(&client, &amm_program_key, &coin_pc_pool).unwrap();
+    let state_coin_pc = load_state(&client, &amm_program_key, &coin_pc_pool)?;
 
-    let coin_in_pc = calc_coin_in_pc(&state_coin_pc.pool).unwrap();
-    let sol_in_usd = calc_coin_in_pc(&state_sol_usd.pool).unwrap();
+    let coin_in_pc = calc_coin_in_pc(&state_coin_pc.pool)?;
+    let sol_in_usd = calc_coin_in_pc(&state_sol_usd.pool)?;
     println!("Sol Price is {} USD", sol_in_usd);
 
     let coin_in_usd = coin_in_pc * sol_in_usd;
     println!("Coin Price is {} USD", coin_in_usd);
 
-    let total_supply = calc_coin_market_cap(&state_coin_pc, &client).unwrap();
+    let total_supply = calc_coin_market_cap(&state_coin_pc, &client)?;
     println!("Total Supply is {}", total_supply);
 
     let mcap = total_supply * coin_in_usd;
@@ -196,53 +234,526 @@ fn fetch_pool_info(
     Ok(state_coin_pc)
 }
 
-fn listen_for_new_pools(url: &String, addresses: Vec<String>) -> Result<()> {
-    let filter = RpcTransactionLogsFilter::Mentions(addresses);
-    let config = RpcTransactionLogsConfig {
-        commitment: Some(CommitmentConfig::finalized()),
-    };
-    let (_pubsub_client_subscription, log_receiver) =
-        PubsubClient::logs_subscribe(url, filter, config).unwrap();
-
-    for log_response in log_receiver {
-        let logs: Vec<String> = log_response.value.logs;
-        if let Some(ray_log_entry) = logs.iter().find(|log| log.contains("ray_log:")) {
-            // Extract the ray_log value
-            if let Some(start) = ray_log_entry.find("ray_log:") {
-                let ray_log_value = &ray_log_entry[start + "ray_log: ".len()..];
-                let bytes = base64::decode_config(ray_log_value, base64::STANDARD).unwrap();
-                let log_type = LogType::from_u8(bytes[0]);
-                if log_type.into_u8() == LogType::Init.into_u8() {
-                    let log: InitLog = bincode::deserialize(&bytes).unwrap();
-                    println!("{:?}", log);
+// the init log has no pool id, the transaction that emitted it does
+fn register_new_pool(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    registry: &PoolRegistry,
+    signature: &str,
+) -> Result<Pubkey> {
+    let signature = Signature::from_str(signature)?;
+    let amm_pool = indexer::fetch_init_pool(client, amm_program_key, &signature)?;
+    let pool_state = load_state(client, amm_program_key, &amm_pool)?;
+    registry.record_new_pool(amm_pool, pool_state);
+    Ok(amm_pool)
+}
+
+fn listen_for_new_pools(
+    url: &String,
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    addresses: Vec<String>,
+    registry: &PoolRegistry,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    logs_subscribe_with_reconnect(
+        url,
+        addresses,
+        CommitmentConfig::finalized(),
+        shutdown,
+        |event| match event {
+            SubscriptionEvent::Message(log_response) => {
+                status.record_event(NEW_POOLS_TASK, log_response.context.slot);
+                let signature = log_response.value.signature;
+                let logs: Vec<String> = log_response.value.logs;
+                if let Some(ray_log_entry) = logs.iter().find(|log| log.contains("ray_log:")) {
+                    // Extract the ray_log value
+                    if let Some(start) = ray_log_entry.find("ray_log:") {
+                        let ray_log_value = &ray_log_entry[start + "ray_log: ".len()..];
+                        let bytes = match base64::decode_config(ray_log_value, base64::STANDARD) {
+                            Ok(bytes) if !bytes.is_empty() => bytes,
+                            _ => return,
+                        };
+                        let log_type = LogType::from_u8(bytes[0]);
+                        if log_type.into_u8() == LogType::Init.into_u8() {
+                            if let Ok(log) = bincode::deserialize::<InitLog>(&bytes) {
+                                println!("{:?}", log);
+                                match register_new_pool(
+                                    client,
+                                    amm_program_key,
+                                    registry,
+                                    &signature,
+                                ) {
+                                    Ok(amm_pool) => println!("new pool {}", amm_pool),
+                                    Err(e) => {
+                                        println!("failed to load new pool {}: {}", signature, e)
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            SubscriptionEvent::Gap(gap) => {
+                status.record_gap(NEW_POOLS_TASK);
+                println!(
+                    "logs gap: slots {}..{} may have been missed",
+                    gap.last_seen + 1,
+                    gap.resumed_at
+                );
+            }
+            SubscriptionEvent::Disconnected => {
+                status.set_status(NEW_POOLS_TASK, TaskStatus::Reconnecting);
+            }
+        },
+    )
+}
+
+// the registry pools are reloaded in one batch every BLOCK_REFRESH_SLOTS slots
+fn listen_for_new_block(
+    url: &String,
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    registry: &PoolRegistry,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let nonblocking_client = NonblockingRpcClient::new(client.url());
+    let mut last_refresh: Option<u64> = None;
+    slot_subscribe_with_reconnect(url, shutdown, |event| match event {
+        SubscriptionEvent::Message(slot_info) => {
+            status.record_event(NEW_BLOCKS_TASK, slot_info.slot);
+            if last_refresh.map_or(false, |last| slot_info.slot < last + BLOCK_REFRESH_SLOTS) {
+                return;
+            }
+            last_refresh = Some(slot_info.slot);
+            let amm_pool_keys = registry.pool_keys();
+            // the old states stay until the next refresh if any pool fails to load
+            match runtime.block_on(load_states(
+                &nonblocking_client,
+                amm_program_key,
+                &amm_pool_keys,
+            )) {
+                Ok(pool_states) => {
+                    for (amm_pool_key, pool_state) in amm_pool_keys.into_iter().zip(pool_states) {
+                        registry.insert(amm_pool_key, pool_state);
+                    }
                 }
+                Err(e) => println!("pool refresh failed: {}", e),
             }
         }
+        SubscriptionEvent::Gap(gap) => {
+            status.record_gap(NEW_BLOCKS_TASK);
+            println!(
+                "slot gap: {} slots missed after {}",
+                gap.missed_slots(),
+                gap.last_seen
+            );
+        }
+        SubscriptionEvent::Disconnected => {
+            status.set_status(NEW_BLOCKS_TASK, TaskStatus::Reconnecting);
+        }
+    })
+}
+
+fn report_status(status: &StatusBoard, registry: &PoolRegistry, shutdown: &AtomicBool) {
+    let mut elapsed = Duration::from_secs(0);
+    while !shutdown.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_secs(1));
+        elapsed += Duration::from_secs(1);
+        if elapsed >= STATUS_REPORT_INTERVAL {
+            elapsed = Duration::from_secs(0);
+            println!(
+                "===================Status ({} pools, {} new)===================",
+                registry.len(),
+                registry.new_pool_count()
+            );
+            status.print();
+        }
+    }
+}
+
+fn spawn_task<F>(name: &'static str, status: &Arc<StatusBoard>, task: F) -> JoinHandle<()>
+where
+    F: FnOnce() -> Result<()> + Send + 'static,
+{
+    status.set_status(name, TaskStatus::Starting);
+    let status = status.clone();
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            let result = task();
+            status.set_status(
+                name,
+                match result {
+                    Ok(()) => TaskStatus::Stopped,
+                    Err(e) => TaskStatus::Failed(e.to_string()),
+                },
+            );
+        })
+        .unwrap()
+}
+
+// reports status until ctrl-c, then waits for the tasks and prints the final status
+fn wait_for_tasks(
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+    registry: &PoolRegistry,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
+) {
+    report_status(status, registry, shutdown);
+
+    for (name, task) in tasks {
+        if task.join().is_err() {
+            status.set_status(name, TaskStatus::Failed("panicked".to_string()));
+        }
     }
+    println!("===================Final Status===================");
+    status.print();
+}
 
+// runs a single watcher task next to the status report
+fn run_task<F>(
+    name: &'static str,
+    registry: &PoolRegistry,
+    status: &Arc<StatusBoard>,
+    shutdown: &Arc<AtomicBool>,
+    task: F,
+) -> Result<()>
+where
+    F: FnOnce(&StatusBoard, Arc<AtomicBool>) -> Result<()> + Send + 'static,
+{
+    let handle = {
+        let (task_status, shutdown) = (status.clone(), shutdown.clone());
+        spawn_task(name, status, move || task(&task_status, shutdown))
+    };
+    wait_for_tasks(vec![(name, handle)], registry, status, shutdown);
     Ok(())
 }
 
-fn listen_for_new_block(
-    url: &String,
+// app index: stream amm events into the sqlite store until ctrl-c
+fn run_index(
+    web_socket_url: String,
+    client: Arc<RpcClient>,
+    amm_program_key: Pubkey,
+    registry: Arc<PoolRegistry>,
+    status: Arc<StatusBoard>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
+    let db_path = env::var("INDEX_DB").unwrap_or_else(|_| DEFAULT_INDEX_DB.to_string());
+    let mut store = EventStore::open(&db_path)?;
+    println!("===================Index into {}===================", db_path);
+    run_task(
+        indexer::INDEXER_TASK,
+        &registry,
+        &status,
+        &shutdown,
+        move |task_status, shutdown| {
+            indexer::run_indexer(
+                &web_socket_url,
+                &client,
+                &amm_program_key,
+                &mut store,
+                task_status,
+                &shutdown,
+            )
+        },
+    )
+}
+
+// app backfill <pool>: rebuild the pool history in the sqlite store, resumable
+fn run_backfill(
     client: &RpcClient,
     amm_program_key: &Pubkey,
-    coin_pc_pool: &Pubkey,
+    amm_pool: &Pubkey,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
 ) -> Result<()> {
-    let (_pubsub_client_subscription, slot_info) = PubsubClient::slot_subscribe(url).unwrap();
+    let db_path = env::var("INDEX_DB").unwrap_or_else(|_| DEFAULT_INDEX_DB.to_string());
+    let mut store = EventStore::open(&db_path)?;
+    println!(
+        "===================Backfill {} into {}===================",
+        amm_pool, db_path
+    );
+    let inserted = indexer::backfill_pool(
+        client,
+        amm_program_key,
+        amm_pool,
+        &mut store,
+        status,
+        shutdown,
+    )?;
+    status.set_status(indexer::BACKFILL_TASK, TaskStatus::Stopped);
+    println!("Backfill stored {} new events", inserted);
+    status.print();
+    Ok(())
+}
 
-    for _slot in slot_info {
-        let _ = fetch_pool_info(&client, &amm_program_key, &coin_pc_pool);
+// app snipe: buy new pools that pass the SNIPE_* filters when they open
+fn run_snipe(
+    web_socket_url: String,
+    client: Arc<RpcClient>,
+    amm_program_key: Pubkey,
+    registry: Arc<PoolRegistry>,
+    status: Arc<StatusBoard>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
+    let config = sniper::SnipeConfig::from_env()?;
+    let payer = Arc::new(config::read_payer()?);
+    println!(
+        "===================Snipe {:?}===================",
+        config.filter
+    );
+    run_task(
+        sniper::SNIPE_TASK,
+        &registry,
+        &status,
+        &shutdown,
+        move |task_status, shutdown| {
+            sniper::run_sniper(
+                &web_socket_url,
+                client,
+                &amm_program_key,
+                &config,
+                payer,
+                task_status,
+                shutdown,
+            )
+        },
+    )
+}
+
+// app position add|list|watch: exits stored positions on take-profit, stop-loss or trailing stop
+fn run_position(
+    args: &[String],
+    web_socket_url: String,
+    client: Arc<RpcClient>,
+    amm_program_key: Pubkey,
+    registry: Arc<PoolRegistry>,
+    status: Arc<StatusBoard>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
+    let db_path = env::var("INDEX_DB").unwrap_or_else(|_| DEFAULT_INDEX_DB.to_string());
+    let book = PositionBook::open(&db_path)?;
+    match args.get(0).map(String::as_str) {
+        Some("add") => {
+            if args.len() != 7 {
+                return Err(format_err!(
+                    "usage: position add <pool> <mint> <amount> <take_profit_bps> <stop_loss_bps> <trailing_stop_bps>"
+                ));
+            }
+            let amm_pool = Pubkey::from_str(&args[1])?;
+            let mint = Pubkey::from_str(&args[2])?;
+            let amount: u64 = args[3].parse()?;
+            // 0 disables the rule
+            let rule = |arg: &String| -> Result<Option<u64>> {
+                let bps: u64 = arg.parse()?;
+                Ok(if bps == 0 { None } else { Some(bps) })
+            };
+            let pool = load_state(&client, &amm_program_key, &amm_pool)?;
+            let entry_price = position_price(&pool, &mint)?;
+            let id = book.add(
+                &amm_pool,
+                &mint,
+                amount,
+                entry_price,
+                rule(&args[4])?,
+                rule(&args[5])?,
+                rule(&args[6])?,
+            )?;
+            println!("position {} opened at {}", id, entry_price);
+            Ok(())
+        }
+        Some("list") => {
+            for position in book.open_positions()? {
+                println!("{:?}", position);
+            }
+            Ok(())
+        }
+        Some("watch") => {
+            let payer = config::read_payer()?;
+            let fee_config = execution::priority_fee_config_from_env()?;
+            let slippage_bps: u64 = config::env_or("POSITION_SLIPPAGE_BPS", 100)?;
+            println!(
+                "===================Watch positions in {}===================",
+                db_path
+            );
+            run_task(
+                positions::POSITIONS_TASK,
+                &registry,
+                &status,
+                &shutdown,
+                move |task_status, shutdown| {
+                    positions::run_position_manager(
+                        &web_socket_url,
+                        &client,
+                        &amm_program_key,
+                        &book,
+                        &payer,
+                        slippage_bps,
+                        &fee_config,
+                        task_status,
+                        &shutdown,
+                    )
+                },
+            )
+        }
+        _ => Err(format_err!("usage: position add|list|watch")),
     }
+}
+
+// app copy: mirror the swaps of COPY_WALLETS with our own size and slippage
+fn run_copy(
+    web_socket_url: String,
+    client: Arc<RpcClient>,
+    amm_program_key: Pubkey,
+    registry: Arc<PoolRegistry>,
+    status: Arc<StatusBoard>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
+    let config = copy_trader::CopyConfig::from_env()?;
+    let payer = config::read_payer()?;
+    println!("===================Copy {:?}===================", config);
+    run_task(
+        copy_trader::COPY_TASK,
+        &registry,
+        &status,
+        &shutdown,
+        move |task_status, shutdown| {
+            copy_trader::run_copy_trader(
+                &web_socket_url,
+                &client,
+                &amm_program_key,
+                &config,
+                &payer,
+                task_status,
+                &shutdown,
+            )
+        },
+    )
+}
 
+// app twap <pool> <input_mint> <total_amount> <children> <duration_secs> [live]: simulates unless live
+fn run_twap(
+    args: &[String],
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    if args.len() < 5 {
+        return Err(format_err!(
+            "usage: twap <pool> <input_mint> <total_amount> <children> <duration_secs> [live]"
+        ));
+    }
+    let config = twap::TwapConfig {
+        amm_pool: Pubkey::from_str(&args[0])?,
+        input_mint: Pubkey::from_str(&args[1])?,
+        total_amount: args[2].parse()?,
+        children: args[3].parse()?,
+        duration: Duration::from_secs(args[4].parse()?),
+        jitter_bps: config::env_or("TWAP_JITTER_BPS", 0)?,
+        max_price_impact_bps: config::env_or("TWAP_MAX_IMPACT_BPS", 100)?,
+        slippage_bps: config::env_or("TWAP_SLIPPAGE_BPS", 100)?,
+        priority_fee: execution::priority_fee_config_from_env()?,
+        dry_run: args.get(5).map(String::as_str) != Some("live"),
+    };
+    // a dry run only simulates, so it takes the owner without its keypair
+    let payer = if config.dry_run {
+        None
+    } else {
+        Some(config::read_payer()?)
+    };
+    let owner = match &payer {
+        Some(payer) => payer.pubkey(),
+        None => Pubkey::from_str(
+            &env::var("TWAP_OWNER")
+                .map_err(|_| format_err!("TWAP_OWNER must be set for a dry run"))?,
+        )?,
+    };
+    println!("===================Twap {:?}===================", config);
+    twap::run_twap(
+        client,
+        amm_program_key,
+        &config,
+        &owner,
+        payer.as_ref(),
+        shutdown,
+    )?;
     Ok(())
 }
 
+// app order add|list|cancel|amend|watch: limit orders filled at or better than their price
+fn run_order(
+    args: &[String],
+    web_socket_url: String,
+    client: Arc<RpcClient>,
+    amm_program_key: Pubkey,
+    registry: Arc<PoolRegistry>,
+    status: Arc<StatusBoard>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
+    let db_path = env::var("INDEX_DB").unwrap_or_else(|_| DEFAULT_INDEX_DB.to_string());
+    let book = OrderBook::open(&db_path)?;
+    match (args.get(0).map(String::as_str), args.len()) {
+        (Some("add"), 5) => {
+            let id = book.add(
+                &Pubkey::from_str(&args[1])?,
+                OrderSide::from_str(&args[2])?,
+                args[3].parse()?,
+                args[4].parse()?,
+            )?;
+            println!("order {} opened", id);
+            Ok(())
+        }
+        (Some("list"), _) => {
+            for order in book.open_orders()? {
+                println!("{:?}", order);
+            }
+            Ok(())
+        }
+        (Some("cancel"), 2) => book.cancel(args[1].parse()?),
+        (Some("amend"), 4) => book.amend(args[1].parse()?, args[2].parse()?, args[3].parse()?),
+        (Some("watch"), _) => {
+            let payer = config::read_payer()?;
+            let fee_config = execution::priority_fee_config_from_env()?;
+            println!(
+                "===================Watch orders in {}===================",
+                db_path
+            );
+            run_task(
+                orders::ORDERS_TASK,
+                &registry,
+                &status,
+                &shutdown,
+                move |task_status, shutdown| {
+                    orders::run_order_watcher(
+                        &web_socket_url,
+                        &client,
+                        &amm_program_key,
+                        &book,
+                        &payer,
+                        &fee_config,
+                        task_status,
+                        &shutdown,
+                    )
+                },
+            )
+        }
+        _ => Err(format_err!(
+            "usage: order add <pool> buy|sell <amount> <limit_price> | list | cancel <id> | amend <id> <amount> <limit_price> | watch"
+        )),
+    }
+}
+
 fn main() -> Result<()> {
     let slippage_bps = 50u64; // 0.5%
     dotenv().ok();
     let rpc_url = env::var("RPC_URL").expect("RPC_URL must be set");
-    let client: RpcClient = RpcClient::new(rpc_url.to_string());
+    let client: Arc<RpcClient> = Arc::new(RpcClient::new(rpc_url.to_string()));
     let web_socket_url = env::var("WEB_SOCKET_URL").expect("WEB_SOCKET_URL must be set");
     let amm_program_key: Pubkey = Pubkey::from_str("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8")?;
     let coin_pc_pool: Pubkey = Pubkey::from_str("879F697iuDJGMevRkRcnW21fcXiAeLJK1ffsw2ATebce")?; // MEW/SOL pool
@@ -251,8 +762,117 @@ fn main() -> Result<()> {
     let out_amount_specific: u64 = 10000000000; // this value includes decimals
     let mut listener_list: Vec<String> = Vec::new();
 
+    let registry = Arc::new(PoolRegistry::new());
+    let status = Arc::new(StatusBoard::new());
+    let shutdown = Arc::new(AtomicBool::new(false));
+    {
+        let shutdown = shutdown.clone();
+        ctrlc::set_handler(move || {
+            println!("Ctrl-C received, shutting down");
+            shutdown.store(true, Ordering::Relaxed);
+        })?;
+    }
+
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("index") => {
+            return run_index(
+                web_socket_url,
+                client,
+                amm_program_key,
+                registry,
+                status,
+                shutdown,
+            )
+        }
+        Some("snipe") => {
+            return run_snipe(
+                web_socket_url,
+                client,
+                amm_program_key,
+                registry,
+                status,
+                shutdown,
+            )
+        }
+        Some("copy") => {
+            return run_copy(
+                web_socket_url,
+                client,
+                amm_program_key,
+                registry,
+                status,
+                shutdown,
+            )
+        }
+        Some("backfill") => {
+            let amm_pool = match args.get(2) {
+                Some(amm_pool) => Pubkey::from_str(amm_pool)?,
+                None => coin_pc_pool,
+            };
+            return run_backfill(&client, &amm_program_key, &amm_pool, &status, &shutdown);
+        }
+        Some("pools") => {
+            let mint = match args.get(2) {
+                Some(mint) => Pubkey::from_str(mint)?,
+                None => return Err(format_err!("usage: pools <mint> [other_mint]")),
+            };
+            let pools = match args.get(3) {
+                Some(other) => {
+                    let other = Pubkey::from_str(other)?;
+                    find_pools_for_pair(&client, &amm_program_key, &mint, &other)?
+                }
+                None => find_pools_for_mint(&client, &amm_program_key, &mint)?,
+            };
+            for (pool, amm_info) in pools.iter() {
+                println!(
+                    "{} coin {} pc {}",
+                    pool, amm_info.coin_vault_mint, amm_info.pc_vault_mint
+                );
+            }
+            return Ok(());
+        }
+        Some("portfolio") => {
+            let wallet = match args.get(2) {
+                Some(wallet) => Pubkey::from_str(wallet)?,
+                None => return Err(format_err!("usage: portfolio <wallet>")),
+            };
+            let sol_usd_pool = Pubkey::from_str(SOL_USD_POOL)?;
+            println!("===================Portfolio {}===================", wallet);
+            let portfolio =
+                portfolio::build_portfolio(&client, &amm_program_key, &wallet, &sol_usd_pool)?;
+            portfolio.print();
+            return Ok(());
+        }
+        Some("twap") => return run_twap(&args[2..], &client, &amm_program_key, &shutdown),
+        Some("order") => {
+            return run_order(
+                &args[2..],
+                web_socket_url,
+                client,
+                amm_program_key,
+                registry,
+                status,
+                shutdown,
+            )
+        }
+        Some("position") => {
+            return run_position(
+                &args[2..],
+                web_socket_url,
+                client,
+                amm_program_key,
+                registry,
+                status,
+                shutdown,
+            )
+        }
+        _ => {}
+    }
+
     println!("===================Fetch Pool Info===================");
     let state_coin_pc = fetch_pool_info(&client, &amm_program_key, &coin_pc_pool)?;
+    registry.insert(coin_pc_pool, state_coin_pc.clone());
 
     println!("===================Simulation Swap Tx===================");
     let _ = simulate_swap_tx(
@@ -265,11 +885,55 @@ fn main() -> Result<()> {
         out_amount_specific,
     );
 
-    println!("===================Listen for new pools===================");
+    println!("===================Listen for new pools and blocks===================");
     listener_list.push(amm_program_key.to_string());
-    let _ = listen_for_new_pools(&web_socket_url, listener_list);
+    let mut tasks: Vec<(&'static str, JoinHandle<()>)> = Vec::new();
+    {
+        let (url, client, registry, task_status, shutdown) = (
+            web_socket_url.clone(),
+            client.clone(),
+            registry.clone(),
+            status.clone(),
+            shutdown.clone(),
+        );
+        tasks.push((
+            NEW_POOLS_TASK,
+            spawn_task(NEW_POOLS_TASK, &status, move || {
+                listen_for_new_pools(
+                    &url,
+                    &client,
+                    &amm_program_key,
+                    listener_list,
+                    &registry,
+                    &task_status,
+                    &shutdown,
+                )
+            }),
+        ));
+    }
+    {
+        let (url, client, registry, task_status, shutdown) = (
+            web_socket_url.clone(),
+            client.clone(),
+            registry.clone(),
+            status.clone(),
+            shutdown.clone(),
+        );
+        tasks.push((
+            NEW_BLOCKS_TASK,
+            spawn_task(NEW_BLOCKS_TASK, &status, move || {
+                listen_for_new_block(
+                    &url,
+                    &client,
+                    &amm_program_key,
+                    &registry,
+                    &task_status,
+                    &shutdown,
+                )
+            }),
+        ));
+    }
 
-    println!("===================Listen for new blocks===================");
-    let _ = listen_for_new_block(&web_socket_url, &client, &amm_program_key, &coin_pc_pool);
+    wait_for_tasks(tasks, &registry, &status, &shutdown);
     Ok(())
 }
@@ -0,0 +1,515 @@
+use anyhow::{format_err, Result};
+use raydium_amm_poc::{
+    amm_math::{
+        calc_coin_in_pc, load_state, quote_swap, swap_exact_amount, CalculateResult, PoolState,
+    },
+    priority_fee::PriorityFeeConfig,
+    raydium_amm::maths::SwapDirection,
+};
+use rusqlite::{params, Connection};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+};
+use std::{collections::HashMap, str::FromStr, sync::atomic::AtomicBool};
+
+use crate::execution::{
+    build_signed_swap_transaction_with_min_out, send_swap_transaction, swap_status, SwapStatus,
+};
+use crate::status::{StatusBoard, TaskStatus};
+use crate::subscription::{slot_subscribe_with_reconnect, SubscriptionEvent};
+
+pub const ORDERS_TASK: &str = "orders";
+// pool prices are reloaded every this many slots
+const PRICE_REFRESH_SLOTS: u64 = 4;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS limit_orders (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pool TEXT NOT NULL,
+    side TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    filled_amount INTEGER NOT NULL DEFAULT 0,
+    limit_price REAL NOT NULL,
+    status TEXT NOT NULL DEFAULT 'open',
+    last_signature TEXT,
+    pending_amount INTEGER,
+    pending_signature TEXT,
+    pending_blockhash TEXT
+);
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderSide {
+    /// pay pc for coin once the price is at or below the limit
+    Buy,
+    /// sell coin for pc once the price is at or above the limit
+    Sell,
+}
+
+impl OrderSide {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderSide::Buy => "buy",
+            OrderSide::Sell => "sell",
+        }
+    }
+
+    pub fn swap_direction(&self) -> SwapDirection {
+        match self {
+            OrderSide::Buy => SwapDirection::PC2Coin,
+            OrderSide::Sell => SwapDirection::Coin2PC,
+        }
+    }
+}
+
+impl FromStr for OrderSide {
+    type Err = anyhow::Error;
+
+    fn from_str(side: &str) -> Result<Self> {
+        match side {
+            "buy" => Ok(OrderSide::Buy),
+            "sell" => Ok(OrderSide::Sell),
+            _ => Err(format_err!("order side must be buy or sell, got {}", side)),
+        }
+    }
+}
+
+// a fill sent but not yet known to have landed
+#[derive(Clone, Debug)]
+pub struct PendingFill {
+    pub amount_in: u64,
+    pub signature: Signature,
+    pub recent_blockhash: Hash,
+}
+
+#[derive(Clone, Debug)]
+pub struct LimitOrder {
+    pub id: i64,
+    pub pool: Pubkey,
+    pub side: OrderSide,
+    /// input left to fill, raw pc units for buys and raw coin units for sells
+    pub amount: u64,
+    pub filled_amount: u64,
+    /// pc per coin, decimals applied
+    pub limit_price: f64,
+    pub pending_fill: Option<PendingFill>,
+}
+
+impl LimitOrder {
+    pub fn triggered(&self, price: f64) -> bool {
+        match self.side {
+            OrderSide::Buy => price <= self.limit_price,
+            OrderSide::Sell => price >= self.limit_price,
+        }
+    }
+}
+
+fn satisfies_limit(state: &CalculateResult, order: &LimitOrder, amount_in: u64) -> Result<bool> {
+    // an input too small to buy anything has no price to compare
+    let amount_out = swap_exact_amount(
+        state.pool_pc_vault_amount,
+        state.pool_coin_vault_amount,
+        state.swap_fee_numerator,
+        state.swap_fee_denominator,
+        order.side.swap_direction(),
+        amount_in,
+        true,
+    )?;
+    if amount_out == 0 {
+        return Ok(false);
+    }
+    let quote = quote_swap(state, order.side.swap_direction(), amount_in, true, 0)?;
+    Ok(match order.side {
+        OrderSide::Buy => quote.execution_price <= order.limit_price,
+        OrderSide::Sell => quote.execution_price >= order.limit_price,
+    })
+}
+
+// largest input up to the order amount whose execution price, fee and impact
+// included, still satisfies the limit
+pub fn fillable_amount(state: &CalculateResult, order: &LimitOrder) -> Result<u64> {
+    if satisfies_limit(state, order, order.amount)? {
+        return Ok(order.amount);
+    }
+    // the execution price only gets worse with size
+    let (mut low, mut high) = (0u64, order.amount);
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if satisfies_limit(state, order, mid)? {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(low)
+}
+
+// output the limit price guarantees for amount_in, capped at the quoted output
+pub fn limit_min_amount_out(
+    state: &CalculateResult,
+    order: &LimitOrder,
+    amount_in: u64,
+) -> Result<u64> {
+    let quote = quote_swap(state, order.side.swap_direction(), amount_in, true, 0)?;
+    let pc_scale = 10_f64.powf(state.pool_pc_decimals as f64);
+    let coin_scale = 10_f64.powf(state.pool_coin_decimals as f64);
+    let min_amount_out = match order.side {
+        OrderSide::Buy => amount_in as f64 / pc_scale / order.limit_price * coin_scale,
+        OrderSide::Sell => amount_in as f64 / coin_scale * order.limit_price * pc_scale,
+    };
+    Ok((min_amount_out.ceil() as u64).min(quote.amount_out))
+}
+
+// orders survive restarts in sqlite
+pub struct OrderBook {
+    conn: Connection,
+}
+
+impl OrderBook {
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(OrderBook { conn })
+    }
+
+    pub fn add(
+        &self,
+        pool: &Pubkey,
+        side: OrderSide,
+        amount: u64,
+        limit_price: f64,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO limit_orders (pool, side, amount, limit_price) VALUES (?1, ?2, ?3, ?4)",
+            params![pool.to_string(), side.as_str(), amount as i64, limit_price],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn open_orders(&self) -> Result<Vec<LimitOrder>> {
+        let mut statement = self.conn.prepare(
+            "SELECT id, pool, side, amount, filled_amount, limit_price,
+                 pending_amount, pending_signature, pending_blockhash
+             FROM limit_orders WHERE status = 'open' ORDER BY id",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, f64>(5)?,
+                (
+                    row.get::<_, Option<i64>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                ),
+            ))
+        })?;
+        let mut orders: Vec<LimitOrder> = Vec::new();
+        for row in rows {
+            let (id, pool, side, amount, filled_amount, limit_price, pending) = row?;
+            let pending_fill = match pending {
+                (Some(amount_in), Some(signature), Some(recent_blockhash)) => Some(PendingFill {
+                    amount_in: amount_in as u64,
+                    signature: Signature::from_str(&signature)?,
+                    recent_blockhash: Hash::from_str(&recent_blockhash)?,
+                }),
+                _ => None,
+            };
+            orders.push(LimitOrder {
+                id,
+                pool: Pubkey::from_str(&pool)?,
+                side: OrderSide::from_str(&side)?,
+                amount: amount as u64,
+                filled_amount: filled_amount as u64,
+                limit_price,
+                pending_fill,
+            });
+        }
+        Ok(orders)
+    }
+
+    fn update_open(&self, id: i64, sql: &str, values: &[&dyn rusqlite::ToSql]) -> Result<()> {
+        let mut all: Vec<&dyn rusqlite::ToSql> = vec![&id];
+        all.extend_from_slice(values);
+        if self.conn.execute(sql, all.as_slice())? == 0 {
+            return Err(format_err!("order {} is not open", id));
+        }
+        Ok(())
+    }
+
+    pub fn cancel(&self, id: i64) -> Result<()> {
+        self.update_open(
+            id,
+            "UPDATE limit_orders SET status = 'cancelled' WHERE id = ?1 AND status = 'open'",
+            &[],
+        )
+    }
+
+    // replaces the unfilled amount and the limit price
+    pub fn amend(&self, id: i64, amount: u64, limit_price: f64) -> Result<()> {
+        self.update_open(
+            id,
+            "UPDATE limit_orders SET amount = ?2, limit_price = ?3 WHERE id = ?1 AND status = 'open'",
+            &[&(amount as i64), &limit_price],
+        )
+    }
+
+    // recorded before the fill is sent, so it is never sent twice
+    pub fn set_pending_fill(&self, id: i64, fill: &PendingFill) -> Result<()> {
+        self.update_open(
+            id,
+            "UPDATE limit_orders SET pending_amount = ?2, pending_signature = ?3, pending_blockhash = ?4
+             WHERE id = ?1 AND status = 'open'",
+            &[
+                &(fill.amount_in as i64),
+                &fill.signature.to_string(),
+                &fill.recent_blockhash.to_string(),
+            ],
+        )
+    }
+
+    pub fn clear_pending_fill(&self, id: i64) -> Result<()> {
+        self.update_open(
+            id,
+            "UPDATE limit_orders SET pending_amount = NULL, pending_signature = NULL, pending_blockhash = NULL
+             WHERE id = ?1 AND status = 'open'",
+            &[],
+        )
+    }
+
+    // the pending fill landed, the order is filled once nothing is left
+    pub fn record_fill(&self, id: i64, amount_in: u64, signature: &str) -> Result<()> {
+        self.update_open(
+            id,
+            "UPDATE limit_orders SET amount = amount - ?2, filled_amount = filled_amount + ?2,
+                 last_signature = ?3,
+                 pending_amount = NULL, pending_signature = NULL, pending_blockhash = NULL,
+                 status = CASE WHEN amount - ?2 <= 0 THEN 'filled' ELSE 'open' END
+             WHERE id = ?1 AND status = 'open'",
+            &[&(amount_in as i64), &signature],
+        )
+    }
+}
+
+// records a fill once it landed, a failed fill is cleared so the order can be
+// filled again
+fn settle_fill(book: &OrderBook, id: i64, fill: &PendingFill, status: SwapStatus) -> Result<()> {
+    match status {
+        SwapStatus::Landed => {
+            println!(
+                "order {} filled {} by {}",
+                id, fill.amount_in, fill.signature
+            );
+            book.record_fill(id, fill.amount_in, &fill.signature.to_string())
+        }
+        SwapStatus::Pending => {
+            println!("order {} fill {} pending", id, fill.signature);
+            Ok(())
+        }
+        SwapStatus::Failed => {
+            println!("order {} fill {} did not land", id, fill.signature);
+            book.clear_pending_fill(id)
+        }
+    }
+}
+
+// fills the part of an order the pool can take at the limit
+fn fill_order(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    book: &OrderBook,
+    payer: &Keypair,
+    fee_config: &PriorityFeeConfig,
+    pool: &PoolState,
+    order: &LimitOrder,
+) -> Result<()> {
+    let amount_in = fillable_amount(&pool.pool, order)?;
+    if amount_in == 0 {
+        return Ok(());
+    }
+    let min_amount_out = limit_min_amount_out(&pool.pool, order, amount_in)?;
+    let input_mint = match order.side {
+        OrderSide::Buy => pool.pool_amm_keys.amm_pc_mint,
+        OrderSide::Sell => pool.pool_amm_keys.amm_coin_mint,
+    };
+    let transaction = build_signed_swap_transaction_with_min_out(
+        client,
+        amm_program_key,
+        pool,
+        payer,
+        &input_mint,
+        amount_in,
+        min_amount_out,
+        fee_config,
+    )?;
+    let fill = PendingFill {
+        amount_in,
+        signature: transaction.signatures[0],
+        recent_blockhash: transaction.message.recent_blockhash,
+    };
+    println!(
+        "order {} {} {} of {} for at least {} by {}",
+        order.id,
+        order.side.as_str(),
+        amount_in,
+        order.amount,
+        min_amount_out,
+        fill.signature
+    );
+    book.set_pending_fill(order.id, &fill)?;
+    let status = send_swap_transaction(client, &transaction)?;
+    settle_fill(book, order.id, &fill, status)
+}
+
+fn check_order(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    book: &OrderBook,
+    payer: &Keypair,
+    fee_config: &PriorityFeeConfig,
+    pools: &mut HashMap<Pubkey, PoolState>,
+    order: &LimitOrder,
+) -> Result<()> {
+    // a fill from an earlier check or run is resolved before filling again
+    if let Some(fill) = &order.pending_fill {
+        let status = swap_status(client, &fill.signature, &fill.recent_blockhash)?;
+        settle_fill(book, order.id, fill, status)?;
+        if status != SwapStatus::Failed {
+            return Ok(());
+        }
+    }
+    if !pools.contains_key(&order.pool) {
+        pools.insert(
+            order.pool,
+            load_state(client, amm_program_key, &order.pool)?,
+        );
+    }
+    let pool = &pools[&order.pool];
+    if !order.triggered(calc_coin_in_pc(&pool.pool)?) {
+        return Ok(());
+    }
+    let filled = fill_order(
+        client,
+        amm_program_key,
+        book,
+        payer,
+        fee_config,
+        pool,
+        order,
+    );
+    // the fill moved the reserves, later orders on the pool need a fresh state
+    pools.remove(&order.pool);
+    filled
+}
+
+fn check_orders(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    book: &OrderBook,
+    payer: &Keypair,
+    fee_config: &PriorityFeeConfig,
+) -> Result<()> {
+    let mut pools: HashMap<Pubkey, PoolState> = HashMap::new();
+    for order in book.open_orders()? {
+        // stays open, the order is checked again on the next refresh
+        if let Err(e) = check_order(
+            client,
+            amm_program_key,
+            book,
+            payer,
+            fee_config,
+            &mut pools,
+            &order,
+        ) {
+            println!("order {} check failed: {}", order.id, e);
+        }
+    }
+    Ok(())
+}
+
+pub fn run_order_watcher(
+    url: &str,
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    book: &OrderBook,
+    payer: &Keypair,
+    fee_config: &PriorityFeeConfig,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    let mut last_refresh: Option<u64> = None;
+    slot_subscribe_with_reconnect(url, shutdown, |event| match event {
+        SubscriptionEvent::Message(slot_info) => {
+            if last_refresh.map_or(false, |last| slot_info.slot < last + PRICE_REFRESH_SLOTS) {
+                return;
+            }
+            last_refresh = Some(slot_info.slot);
+            match check_orders(client, amm_program_key, book, payer, fee_config) {
+                Ok(()) => status.record_event(ORDERS_TASK, slot_info.slot),
+                Err(e) => println!("order check failed: {}", e),
+            }
+        }
+        SubscriptionEvent::Gap(_) => status.record_gap(ORDERS_TASK),
+        SubscriptionEvent::Disconnected => {
+            status.set_status(ORDERS_TASK, TaskStatus::Reconnecting);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raydium_amm_poc::fixtures;
+
+    fn pool() -> CalculateResult {
+        fixtures::pool(1_000_000, 1_000_000)
+    }
+
+    fn order(side: OrderSide, amount: u64, limit_price: f64) -> LimitOrder {
+        LimitOrder {
+            id: 1,
+            pool: Pubkey::new_unique(),
+            side,
+            amount,
+            filled_amount: 0,
+            limit_price,
+            pending_fill: None,
+        }
+    }
+
+    #[test]
+    fn fillable_amount_fills_whole_order_within_limit() {
+        // 100_000 pc buys 90_702 coin, about 1.1025 pc per coin
+        let order = order(OrderSide::Buy, 100_000, 1.2);
+        assert_eq!(fillable_amount(&pool(), &order).unwrap(), 100_000);
+    }
+
+    #[test]
+    fn fillable_amount_stops_where_price_crosses_limit() {
+        let state = pool();
+        for order in [
+            order(OrderSide::Buy, 100_000, 1.05),
+            order(OrderSide::Sell, 100_000, 0.95),
+        ]
+        .iter()
+        {
+            let amount = fillable_amount(&state, order).unwrap();
+            assert!(amount > 0 && amount < order.amount);
+            assert!(satisfies_limit(&state, order, amount).unwrap());
+            assert!(!satisfies_limit(&state, order, amount + 1).unwrap());
+        }
+    }
+
+    #[test]
+    fn fillable_amount_is_zero_past_the_spot_price() {
+        // the fee alone puts every buy above 1 pc per coin
+        let order = order(OrderSide::Buy, 100_000, 0.99);
+        assert_eq!(fillable_amount(&pool(), &order).unwrap(), 0);
+    }
+}
@@ -0,0 +1,370 @@
+use anyhow::Result;
+use raydium_amm_poc::{
+    amm_math::{calc_coin_in_pc, load_state, CalculateResult},
+    pool_discovery::{
+        find_pool_for_lp_mint, find_pools, find_pools_for_mint, sort_by_liquidity, AmmMintField,
+    },
+    raydium_amm::{processor::AUTHORITY_AMM, state::AmmInfo},
+    token::fetch_mint_infos,
+};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{native_token::lamports_to_sol, program_pack::Pack, pubkey::Pubkey};
+use spl_token::native_mint;
+use spl_token_2022::{extension::StateWithExtensions, state::Account};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::positions::position_price;
+
+// owner follows the mint in spl token and token-2022 accounts
+const TOKEN_ACCOUNT_OWNER_OFFSET: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct Holding {
+    pub mint: Pubkey,
+    /// raw units summed over the wallet's accounts of the mint
+    pub amount: u64,
+    pub decimals: u8,
+    /// pool the price came from, the lp pool for lp tokens
+    pub pool: Option<Pubkey>,
+    pub is_lp: bool,
+    /// sol per whole token, none when no pool prices the mint
+    pub price_sol: Option<f64>,
+}
+
+impl Holding {
+    pub fn ui_amount(&self) -> f64 {
+        self.amount as f64 / 10_f64.powf(self.decimals as f64)
+    }
+
+    pub fn value_sol(&self) -> Option<f64> {
+        self.price_sol.map(|price| price * self.ui_amount())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Portfolio {
+    pub wallet: Pubkey,
+    /// native balance, wrapped sol is a holding
+    pub lamports: u64,
+    pub holdings: Vec<Holding>,
+    /// usd per sol from the reference pool
+    pub sol_usd: f64,
+}
+
+impl Portfolio {
+    pub fn total_sol(&self) -> f64 {
+        lamports_to_sol(self.lamports)
+            + self
+                .holdings
+                .iter()
+                .filter_map(|holding| holding.value_sol())
+                .sum::<f64>()
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{:<44} {:>20} {:>16} {:>14}",
+            "mint", "amount", "sol", "usd"
+        );
+        let sol = lamports_to_sol(self.lamports);
+        println!(
+            "{:<44} {:>20.9} {:>16.6} {:>14.2}",
+            "SOL",
+            sol,
+            sol,
+            sol * self.sol_usd
+        );
+        for holding in self.holdings.iter() {
+            let label = if holding.is_lp {
+                format!("{} (lp)", holding.mint)
+            } else {
+                holding.mint.to_string()
+            };
+            match holding.value_sol() {
+                Some(value) => println!(
+                    "{:<44} {:>20.9} {:>16.6} {:>14.2}",
+                    label,
+                    holding.ui_amount(),
+                    value,
+                    value * self.sol_usd
+                ),
+                None => println!(
+                    "{:<44} {:>20.9} {:>16} {:>14}",
+                    label,
+                    holding.ui_amount(),
+                    "no pool",
+                    "-"
+                ),
+            }
+        }
+        let total = self.total_sol();
+        println!(
+            "total {} SOL, {} USD (1 SOL = {} USD)",
+            total,
+            total * self.sol_usd,
+            self.sol_usd
+        );
+    }
+}
+
+fn base64_accounts(filters: Vec<RpcFilterType>) -> RpcProgramAccountsConfig {
+    RpcProgramAccountsConfig {
+        filters: Some(filters),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    }
+}
+
+// (mint, raw amount) of every spl token and token-2022 account of the wallet,
+// zero balances skipped
+fn token_balances(client: &RpcClient, wallet: &Pubkey) -> Result<BTreeMap<Pubkey, u64>> {
+    let owned = RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+        TOKEN_ACCOUNT_OWNER_OFFSET,
+        wallet.as_ref(),
+    ));
+    let queries = [
+        (
+            spl_token::ID,
+            vec![RpcFilterType::DataSize(Account::LEN as u64), owned.clone()],
+        ),
+        // token-2022 accounts grow with their extensions
+        (spl_token_2022::ID, vec![owned]),
+    ];
+    let mut balances: BTreeMap<Pubkey, u64> = BTreeMap::new();
+    for (token_program, filters) in queries.iter() {
+        let accounts = client
+            .get_program_accounts_with_config(token_program, base64_accounts(filters.clone()))?;
+        for (_, account) in accounts.iter() {
+            // skips anything matched that is not a token account
+            let account = match StateWithExtensions::<Account>::unpack(&account.data) {
+                Ok(state) => state.base,
+                Err(_) => continue,
+            };
+            if account.amount > 0 {
+                *balances.entry(account.mint).or_default() += account.amount;
+            }
+        }
+    }
+    Ok(balances)
+}
+
+// sol prices of many mints. the sol pools of every mint come from one program
+// scan per side, and a price is worked out once however often it is asked for
+struct SolPrices<'a> {
+    client: &'a RpcClient,
+    amm_program_key: &'a Pubkey,
+    sol_pools: HashMap<Pubkey, Vec<(Pubkey, AmmInfo)>>,
+    prices: HashMap<Pubkey, Option<(f64, Option<Pubkey>)>>,
+}
+
+impl<'a> SolPrices<'a> {
+    fn new(client: &'a RpcClient, amm_program_key: &'a Pubkey) -> Result<Self> {
+        let sol = native_mint::id();
+        let mut sol_pools: HashMap<Pubkey, Vec<(Pubkey, AmmInfo)>> = HashMap::new();
+        for (pool, amm_info) in find_pools(client, amm_program_key, &[(AmmMintField::Coin, &sol)])?
+        {
+            sol_pools
+                .entry(amm_info.pc_vault_mint)
+                .or_default()
+                .push((pool, amm_info));
+        }
+        for (pool, amm_info) in find_pools(client, amm_program_key, &[(AmmMintField::Pc, &sol)])? {
+            sol_pools
+                .entry(amm_info.coin_vault_mint)
+                .or_default()
+                .push((pool, amm_info));
+        }
+        Ok(SolPrices {
+            client,
+            amm_program_key,
+            sol_pools,
+            prices: HashMap::new(),
+        })
+    }
+
+    // through the deepest mint/sol pool, one that fails to load falls through to the next
+    fn direct(&self, mint: &Pubkey) -> Result<Option<(f64, Option<Pubkey>)>> {
+        if *mint == native_mint::id() {
+            return Ok(Some((1.0, None)));
+        }
+        let pools = match self.sol_pools.get(mint) {
+            Some(pools) => sort_by_liquidity(self.client, pools.clone(), &native_mint::id())?,
+            None => return Ok(None),
+        };
+        for (pool, _) in pools.iter() {
+            let state = match load_state(self.client, self.amm_program_key, pool) {
+                Ok(state) => state,
+                Err(_) => continue,
+            };
+            if let Ok(price) = position_price(&state, mint) {
+                return Ok(Some((price, Some(*pool))));
+            }
+        }
+        Ok(None)
+    }
+
+    // sol per whole token through a mint/sol pool, or one hop through any pool
+    // of the mint. only the hop scans the program for the mint alone
+    fn price(&mut self, mint: &Pubkey) -> Result<Option<(f64, Option<Pubkey>)>> {
+        if let Some(price) = self.prices.get(mint) {
+            return Ok(*price);
+        }
+        let mut price = self.direct(mint)?;
+        if price.is_none() {
+            for (pool, _) in find_pools_for_mint(self.client, self.amm_program_key, mint)?.iter() {
+                let state = match load_state(self.client, self.amm_program_key, pool) {
+                    Ok(state) => state,
+                    Err(_) => continue,
+                };
+                let other = if state.pool_amm_keys.amm_coin_mint == *mint {
+                    state.pool_amm_keys.amm_pc_mint
+                } else {
+                    state.pool_amm_keys.amm_coin_mint
+                };
+                if let Some((other_price, _)) = self.direct(&other)? {
+                    price = Some((position_price(&state, mint)? * other_price, Some(*pool)));
+                    break;
+                }
+            }
+        }
+        self.prices.insert(*mint, price);
+        Ok(price)
+    }
+
+    // sol per whole lp token, both reserves valued through their own sol price
+    fn lp_price(&mut self, pool: &Pubkey, lp_decimals: u8) -> Result<Option<f64>> {
+        let state = load_state(self.client, self.amm_program_key, pool)?;
+        let keys = &state.pool_amm_keys;
+        match (
+            self.price(&keys.amm_coin_mint)?,
+            self.price(&keys.amm_pc_mint)?,
+        ) {
+            (Some((coin_price, _)), Some((pc_price, _))) => {
+                Ok(lp_sol_price(&state.pool, coin_price, pc_price, lp_decimals))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+// reserves in sol over the lp supply, none without lp tokens out
+fn lp_sol_price(
+    pool: &CalculateResult,
+    coin_price: f64,
+    pc_price: f64,
+    lp_decimals: u8,
+) -> Option<f64> {
+    if pool.pool_lp_amount == 0 {
+        return None;
+    }
+    let reserves_sol = pool.pool_coin_vault_amount as f64
+        / 10_f64.powf(pool.pool_coin_decimals as f64)
+        * coin_price
+        + pool.pool_pc_vault_amount as f64 / 10_f64.powf(pool.pool_pc_decimals as f64) * pc_price;
+    let lp_supply = pool.pool_lp_amount as f64 / 10_f64.powf(lp_decimals as f64);
+    Some(reserves_sol / lp_supply)
+}
+
+// every spl token holding of the wallet valued in sol through raydium v4 pools
+pub fn build_portfolio(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    wallet: &Pubkey,
+    sol_usd_pool: &Pubkey,
+) -> Result<Portfolio> {
+    let balances = token_balances(client, wallet)?;
+    let mints: Vec<Pubkey> = balances.keys().cloned().collect();
+    let mint_infos = fetch_mint_infos(client, &mints)?;
+    // the amm authority mints every lp token, so other mints skip the lp scan
+    let amm_authority = Pubkey::find_program_address(&[AUTHORITY_AMM], amm_program_key).0;
+    let mut prices = SolPrices::new(client, amm_program_key)?;
+    let mut holdings: Vec<Holding> = Vec::new();
+    for (info, (mint, amount)) in mint_infos.iter().zip(balances.iter()) {
+        let mut holding = Holding {
+            mint: *mint,
+            amount: *amount,
+            decimals: info.decimals,
+            pool: None,
+            is_lp: false,
+            price_sol: None,
+        };
+        let lp_pool = if info.mint_authority == Some(amm_authority) {
+            find_pool_for_lp_mint(client, amm_program_key, mint)?
+        } else {
+            None
+        };
+        if let Some((pool, _)) = lp_pool {
+            holding.is_lp = true;
+            holding.pool = Some(pool);
+            holding.price_sol = prices.lp_price(&pool, info.decimals)?;
+        } else if let Some((price, pool)) = prices.price(mint)? {
+            holding.pool = pool;
+            holding.price_sol = Some(price);
+        }
+        holdings.push(holding);
+    }
+    let sol_usd_state = load_state(client, amm_program_key, sol_usd_pool)?;
+    Ok(Portfolio {
+        wallet: *wallet,
+        lamports: client.get_balance(wallet)?,
+        holdings,
+        sol_usd: calc_coin_in_pc(&sol_usd_state.pool)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raydium_amm_poc::fixtures::pool;
+
+    fn holding(amount: u64, decimals: u8, price_sol: Option<f64>) -> Holding {
+        Holding {
+            mint: Pubkey::new_unique(),
+            amount,
+            decimals,
+            pool: None,
+            is_lp: false,
+            price_sol,
+        }
+    }
+
+    #[test]
+    fn holding_value_uses_whole_tokens() {
+        assert_eq!(holding(2_500_000, 6, Some(0.4)).value_sol(), Some(1.0));
+        assert_eq!(holding(7, 0, Some(0.5)).value_sol(), Some(3.5));
+        assert_eq!(holding(1_000, 3, None).value_sol(), None);
+    }
+
+    #[test]
+    fn lp_price_values_both_reserves() {
+        let mut lp_pool = pool(4_000, 1_000);
+        lp_pool.pool_lp_amount = 2_000;
+        // 1000 coin at 0.5 and 4000 pc at 0.25 over 2000 lp tokens
+        assert_eq!(lp_sol_price(&lp_pool, 0.5, 0.25, 0), Some(0.75));
+        // the same supply read with 3 lp decimals is 2 whole tokens
+        assert_eq!(lp_sol_price(&lp_pool, 0.5, 0.25, 3), Some(750.0));
+    }
+
+    #[test]
+    fn lp_price_reads_reserve_decimals() {
+        let mut lp_pool = pool(4_000_000, 1_000_000_000);
+        lp_pool.pool_coin_decimals = 9;
+        lp_pool.pool_pc_decimals = 6;
+        lp_pool.pool_lp_amount = 10;
+        // 1 coin at 2 sol and 4 pc at 0.5 sol over 10 lp tokens
+        assert_eq!(lp_sol_price(&lp_pool, 2.0, 0.5, 0), Some(0.4));
+    }
+
+    #[test]
+    fn lp_price_needs_lp_supply() {
+        assert_eq!(lp_sol_price(&pool(4_000, 1_000), 0.5, 0.25, 0), None);
+    }
+}
@@ -0,0 +1,583 @@
+use anyhow::{format_err, Result};
+use raydium_amm_poc::{
+    amm_math::{calc_coin_in_pc, load_state, PoolState},
+    priority_fee::PriorityFeeConfig,
+};
+use rusqlite::{params, Connection};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+};
+use std::{collections::HashMap, str::FromStr, sync::atomic::AtomicBool};
+
+use crate::execution::{
+    build_signed_swap_transaction, send_swap_transaction, swap_status, SwapStatus,
+};
+use crate::status::{StatusBoard, TaskStatus};
+use crate::subscription::{slot_subscribe_with_reconnect, SubscriptionEvent};
+
+pub const POSITIONS_TASK: &str = "positions";
+// pool prices are reloaded every this many slots
+const PRICE_REFRESH_SLOTS: u64 = 4;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS positions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pool TEXT NOT NULL,
+    mint TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    entry_price REAL NOT NULL,
+    peak_price REAL NOT NULL,
+    take_profit_bps INTEGER,
+    stop_loss_bps INTEGER,
+    trailing_stop_bps INTEGER,
+    closed INTEGER NOT NULL DEFAULT 0,
+    exit_reason TEXT,
+    exit_signature TEXT,
+    exit_blockhash TEXT
+);
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitReason {
+    TakeProfit,
+    StopLoss,
+    TrailingStop,
+}
+
+impl ExitReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExitReason::TakeProfit => "take-profit",
+            ExitReason::StopLoss => "stop-loss",
+            ExitReason::TrailingStop => "trailing-stop",
+        }
+    }
+}
+
+impl FromStr for ExitReason {
+    type Err = anyhow::Error;
+
+    fn from_str(reason: &str) -> Result<Self> {
+        match reason {
+            "take-profit" => Ok(ExitReason::TakeProfit),
+            "stop-loss" => Ok(ExitReason::StopLoss),
+            "trailing-stop" => Ok(ExitReason::TrailingStop),
+            _ => Err(format_err!("unknown exit reason {}", reason)),
+        }
+    }
+}
+
+// an exit sent but not yet known to have landed
+#[derive(Clone, Debug)]
+pub struct PendingExit {
+    pub reason: ExitReason,
+    pub signature: Signature,
+    pub recent_blockhash: Hash,
+}
+
+#[derive(Clone, Debug)]
+pub struct Position {
+    pub id: i64,
+    pub pool: Pubkey,
+    /// the token held, sold for the other pool mint on exit
+    pub mint: Pubkey,
+    /// raw units
+    pub amount: u64,
+    /// other mint per held token, decimals applied
+    pub entry_price: f64,
+    /// highest price seen while open, drives the trailing stop
+    pub peak_price: f64,
+    pub take_profit_bps: Option<u64>,
+    pub stop_loss_bps: Option<u64>,
+    pub trailing_stop_bps: Option<u64>,
+    pub pending_exit: Option<PendingExit>,
+}
+
+impl Position {
+    // take profit first, a price can not both rise above it and fall below a stop
+    pub fn exit_reason(&self, price: f64) -> Option<ExitReason> {
+        let bps = |bps: u64| bps as f64 / 10000.0;
+        if let Some(take_profit_bps) = self.take_profit_bps {
+            if price >= self.entry_price * (1.0 + bps(take_profit_bps)) {
+                return Some(ExitReason::TakeProfit);
+            }
+        }
+        if let Some(stop_loss_bps) = self.stop_loss_bps {
+            if price <= self.entry_price * (1.0 - bps(stop_loss_bps)) {
+                return Some(ExitReason::StopLoss);
+            }
+        }
+        if let Some(trailing_stop_bps) = self.trailing_stop_bps {
+            if price <= self.peak_price * (1.0 - bps(trailing_stop_bps)) {
+                return Some(ExitReason::TrailingStop);
+            }
+        }
+        None
+    }
+}
+
+// price of mint in the other pool mint
+pub fn position_price(pool: &PoolState, mint: &Pubkey) -> Result<f64> {
+    let coin_in_pc = calc_coin_in_pc(&pool.pool)?;
+    if *mint == pool.pool_amm_keys.amm_coin_mint {
+        Ok(coin_in_pc)
+    } else if *mint == pool.pool_amm_keys.amm_pc_mint {
+        Ok(1.0 / coin_in_pc)
+    } else {
+        Err(format_err!(
+            "pool {} does not trade {}",
+            pool.pool_amm_keys.amm_pool,
+            mint
+        ))
+    }
+}
+
+fn optional_bps(bps: Option<i64>) -> Option<u64> {
+    bps.map(|bps| bps as u64)
+}
+
+// open positions survive restarts in sqlite
+pub struct PositionBook {
+    conn: Connection,
+}
+
+impl PositionBook {
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(PositionBook { conn })
+    }
+
+    pub fn add(
+        &self,
+        pool: &Pubkey,
+        mint: &Pubkey,
+        amount: u64,
+        entry_price: f64,
+        take_profit_bps: Option<u64>,
+        stop_loss_bps: Option<u64>,
+        trailing_stop_bps: Option<u64>,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO positions (pool, mint, amount, entry_price, peak_price, take_profit_bps, stop_loss_bps, trailing_stop_bps)
+             VALUES (?1, ?2, ?3, ?4, ?4, ?5, ?6, ?7)",
+            params![
+                pool.to_string(),
+                mint.to_string(),
+                amount as i64,
+                entry_price,
+                take_profit_bps.map(|bps| bps as i64),
+                stop_loss_bps.map(|bps| bps as i64),
+                trailing_stop_bps.map(|bps| bps as i64),
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn open_positions(&self) -> Result<Vec<Position>> {
+        let mut statement = self.conn.prepare(
+            "SELECT id, pool, mint, amount, entry_price, peak_price, take_profit_bps, stop_loss_bps, trailing_stop_bps,
+                 exit_reason, exit_signature, exit_blockhash
+             FROM positions WHERE closed = 0 ORDER BY id",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, f64>(4)?,
+                row.get::<_, f64>(5)?,
+                row.get::<_, Option<i64>>(6)?,
+                row.get::<_, Option<i64>>(7)?,
+                row.get::<_, Option<i64>>(8)?,
+                (
+                    row.get::<_, Option<String>>(9)?,
+                    row.get::<_, Option<String>>(10)?,
+                    row.get::<_, Option<String>>(11)?,
+                ),
+            ))
+        })?;
+        let mut positions: Vec<Position> = Vec::new();
+        for row in rows {
+            let (
+                id,
+                pool,
+                mint,
+                amount,
+                entry_price,
+                peak_price,
+                take_profit,
+                stop_loss,
+                trailing,
+                exit,
+            ) = row?;
+            let pending_exit = match exit {
+                (Some(reason), Some(signature), Some(recent_blockhash)) => Some(PendingExit {
+                    reason: ExitReason::from_str(&reason)?,
+                    signature: Signature::from_str(&signature)?,
+                    recent_blockhash: Hash::from_str(&recent_blockhash)?,
+                }),
+                _ => None,
+            };
+            positions.push(Position {
+                id,
+                pool: Pubkey::from_str(&pool)?,
+                mint: Pubkey::from_str(&mint)?,
+                amount: amount as u64,
+                entry_price,
+                peak_price,
+                take_profit_bps: optional_bps(take_profit),
+                stop_loss_bps: optional_bps(stop_loss),
+                trailing_stop_bps: optional_bps(trailing),
+                pending_exit,
+            });
+        }
+        Ok(positions)
+    }
+
+    pub fn update_peak_price(&self, id: i64, peak_price: f64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE positions SET peak_price = ?2 WHERE id = ?1",
+            params![id, peak_price],
+        )?;
+        Ok(())
+    }
+
+    // recorded before the exit is sent, so it is never sent twice
+    pub fn set_pending_exit(&self, id: i64, exit: &PendingExit) -> Result<()> {
+        self.conn.execute(
+            "UPDATE positions SET exit_reason = ?2, exit_signature = ?3, exit_blockhash = ?4 WHERE id = ?1",
+            params![
+                id,
+                exit.reason.as_str(),
+                exit.signature.to_string(),
+                exit.recent_blockhash.to_string()
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_pending_exit(&self, id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE positions SET exit_reason = NULL, exit_signature = NULL, exit_blockhash = NULL WHERE id = ?1",
+            params![id],
+        )?;
+        Ok(())
+    }
+
+    // the pending exit landed
+    pub fn close(&self, id: i64) -> Result<()> {
+        self.conn
+            .execute("UPDATE positions SET closed = 1 WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+}
+
+// closes the position once its exit landed, a failed exit is cleared so the
+// rule can fire again
+fn settle_exit(book: &PositionBook, id: i64, exit: &PendingExit, status: SwapStatus) -> Result<()> {
+    match status {
+        SwapStatus::Landed => {
+            println!(
+                "position {} closed by {} ({})",
+                id,
+                exit.signature,
+                exit.reason.as_str()
+            );
+            book.close(id)
+        }
+        SwapStatus::Pending => {
+            println!("position {} exit {} pending", id, exit.signature);
+            Ok(())
+        }
+        SwapStatus::Failed => {
+            println!("position {} exit {} did not land", id, exit.signature);
+            book.clear_pending_exit(id)
+        }
+    }
+}
+
+fn check_position(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    book: &PositionBook,
+    payer: &Keypair,
+    slippage_bps: u64,
+    fee_config: &PriorityFeeConfig,
+    pools: &mut HashMap<Pubkey, PoolState>,
+    mut position: Position,
+) -> Result<()> {
+    // an exit from an earlier check or run is resolved before selling again
+    if let Some(exit) = position.pending_exit.take() {
+        let status = swap_status(client, &exit.signature, &exit.recent_blockhash)?;
+        settle_exit(book, position.id, &exit, status)?;
+        if status != SwapStatus::Failed {
+            return Ok(());
+        }
+    }
+    if !pools.contains_key(&position.pool) {
+        pools.insert(
+            position.pool,
+            load_state(client, amm_program_key, &position.pool)?,
+        );
+    }
+    let pool = &pools[&position.pool];
+    let price = position_price(pool, &position.mint)?;
+    if price > position.peak_price {
+        position.peak_price = price;
+        book.update_peak_price(position.id, price)?;
+    }
+    let reason = match position.exit_reason(price) {
+        Some(reason) => reason,
+        None => return Ok(()),
+    };
+    println!(
+        "position {} {} at {} (entry {}, peak {})",
+        position.id,
+        reason.as_str(),
+        price,
+        position.entry_price,
+        position.peak_price
+    );
+    let (transaction, _) = build_signed_swap_transaction(
+        client,
+        amm_program_key,
+        pool,
+        payer,
+        &position.mint,
+        position.amount,
+        slippage_bps,
+        fee_config,
+    )?;
+    let exit = PendingExit {
+        reason,
+        signature: transaction.signatures[0],
+        recent_blockhash: transaction.message.recent_blockhash,
+    };
+    book.set_pending_exit(position.id, &exit)?;
+    let status = send_swap_transaction(client, &transaction)?;
+    // the sale moved the reserves
+    pools.remove(&position.pool);
+    settle_exit(book, position.id, &exit, status)
+}
+
+// reloads the price of every open position and exits the ones whose rule fires
+fn check_positions(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    book: &PositionBook,
+    payer: &Keypair,
+    slippage_bps: u64,
+    fee_config: &PriorityFeeConfig,
+) -> Result<()> {
+    let mut pools: HashMap<Pubkey, PoolState> = HashMap::new();
+    for position in book.open_positions()? {
+        let id = position.id;
+        // stays open, a position whose pool failed to load is checked again on the next refresh
+        if let Err(e) = check_position(
+            client,
+            amm_program_key,
+            book,
+            payer,
+            slippage_bps,
+            fee_config,
+            &mut pools,
+            position,
+        ) {
+            println!("position {} check failed: {}", id, e);
+        }
+    }
+    Ok(())
+}
+
+pub fn run_position_manager(
+    url: &str,
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    book: &PositionBook,
+    payer: &Keypair,
+    slippage_bps: u64,
+    fee_config: &PriorityFeeConfig,
+    status: &StatusBoard,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    let mut last_refresh: Option<u64> = None;
+    slot_subscribe_with_reconnect(url, shutdown, |event| match event {
+        SubscriptionEvent::Message(slot_info) => {
+            if last_refresh.map_or(false, |last| slot_info.slot < last + PRICE_REFRESH_SLOTS) {
+                return;
+            }
+            last_refresh = Some(slot_info.slot);
+            match check_positions(
+                client,
+                amm_program_key,
+                book,
+                payer,
+                slippage_bps,
+                fee_config,
+            ) {
+                Ok(()) => status.record_event(POSITIONS_TASK, slot_info.slot),
+                Err(e) => println!("position check failed: {}", e),
+            }
+        }
+        SubscriptionEvent::Gap(_) => status.record_gap(POSITIONS_TASK),
+        SubscriptionEvent::Disconnected => {
+            status.set_status(POSITIONS_TASK, TaskStatus::Reconnecting);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raydium_amm_poc::fixtures::pool_state;
+
+    fn position(
+        take_profit_bps: Option<u64>,
+        stop_loss_bps: Option<u64>,
+        trailing_stop_bps: Option<u64>,
+    ) -> Position {
+        Position {
+            id: 1,
+            pool: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            amount: 1_000,
+            entry_price: 1.0,
+            peak_price: 2.0,
+            take_profit_bps,
+            stop_loss_bps,
+            trailing_stop_bps,
+            pending_exit: None,
+        }
+    }
+
+    // a take profit position with nothing else set
+    fn add(book: &PositionBook) -> i64 {
+        book.add(
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            1,
+            1.0,
+            Some(100),
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn pending_exit(reason: ExitReason) -> PendingExit {
+        PendingExit {
+            reason,
+            signature: Signature::new_unique(),
+            recent_blockhash: Hash::new_unique(),
+        }
+    }
+
+    #[test]
+    fn position_price_is_quoted_in_the_other_mint() {
+        let (coin, pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let pool = pool_state(coin, pc, 1_000, 4_000);
+        assert_eq!(position_price(&pool, &coin).unwrap(), 4.0);
+        assert_eq!(position_price(&pool, &pc).unwrap(), 0.25);
+        assert!(position_price(&pool, &Pubkey::new_unique()).is_err());
+    }
+
+    #[test]
+    fn take_profit_fires_at_or_above_its_price() {
+        let position = position(Some(5_000), None, None);
+        assert_eq!(position.exit_reason(1.49), None);
+        assert_eq!(position.exit_reason(1.5), Some(ExitReason::TakeProfit));
+        assert_eq!(position.exit_reason(3.0), Some(ExitReason::TakeProfit));
+    }
+
+    #[test]
+    fn stop_loss_fires_below_the_entry() {
+        let position = position(None, Some(2_000), None);
+        assert_eq!(position.exit_reason(0.81), None);
+        assert_eq!(position.exit_reason(0.79), Some(ExitReason::StopLoss));
+    }
+
+    #[test]
+    fn trailing_stop_follows_the_peak() {
+        // 10% under the 2.0 peak, even though still above the entry
+        let position = position(None, None, Some(1_000));
+        assert_eq!(position.exit_reason(1.81), None);
+        assert_eq!(position.exit_reason(1.79), Some(ExitReason::TrailingStop));
+    }
+
+    #[test]
+    fn take_profit_wins_and_no_rules_never_exit() {
+        let mut every_rule = position(Some(5_000), Some(2_000), Some(1_000));
+        // the stop loss is checked before the trailing stop
+        assert_eq!(every_rule.exit_reason(0.5), Some(ExitReason::StopLoss));
+        // above take profit and 60% under the peak
+        every_rule.peak_price = 4.0;
+        assert_eq!(every_rule.exit_reason(1.6), Some(ExitReason::TakeProfit));
+
+        let no_rules = position(None, None, None);
+        assert_eq!(no_rules.exit_reason(0.0), None);
+        assert_eq!(no_rules.exit_reason(100.0), None);
+    }
+
+    #[test]
+    fn positions_round_trip_through_sqlite() {
+        let book = PositionBook::open(":memory:").unwrap();
+        let (pool, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let id = book
+            .add(&pool, &mint, 1_000, 0.5, Some(5_000), None, Some(1_000))
+            .unwrap();
+        book.update_peak_price(id, 0.75).unwrap();
+        let positions = book.open_positions().unwrap();
+        assert_eq!(positions.len(), 1);
+        let position = &positions[0];
+        assert_eq!(position.id, id);
+        assert_eq!(position.pool, pool);
+        assert_eq!(position.mint, mint);
+        assert_eq!(position.amount, 1_000);
+        assert_eq!(position.entry_price, 0.5);
+        assert_eq!(position.peak_price, 0.75);
+        assert_eq!(position.take_profit_bps, Some(5_000));
+        assert_eq!(position.stop_loss_bps, None);
+        assert_eq!(position.trailing_stop_bps, Some(1_000));
+        assert!(position.pending_exit.is_none());
+    }
+
+    #[test]
+    fn pending_exit_survives_a_reload() {
+        let book = PositionBook::open(":memory:").unwrap();
+        let id = add(&book);
+        let exit = pending_exit(ExitReason::StopLoss);
+        book.set_pending_exit(id, &exit).unwrap();
+        let positions = book.open_positions().unwrap();
+        let reloaded = positions[0].pending_exit.as_ref().unwrap();
+        assert_eq!(reloaded.reason, ExitReason::StopLoss);
+        assert_eq!(reloaded.signature, exit.signature);
+        assert_eq!(reloaded.recent_blockhash, exit.recent_blockhash);
+    }
+
+    #[test]
+    fn settle_exit_closes_keeps_or_clears() {
+        let book = PositionBook::open(":memory:").unwrap();
+        let (landed, pending, failed) = (add(&book), add(&book), add(&book));
+        for id in [landed, pending, failed].iter() {
+            book.set_pending_exit(*id, &pending_exit(ExitReason::TakeProfit))
+                .unwrap();
+        }
+        let exit = pending_exit(ExitReason::TakeProfit);
+        settle_exit(&book, landed, &exit, SwapStatus::Landed).unwrap();
+        settle_exit(&book, pending, &exit, SwapStatus::Pending).unwrap();
+        settle_exit(&book, failed, &exit, SwapStatus::Failed).unwrap();
+
+        let positions = book.open_positions().unwrap();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[0].id, pending);
+        assert!(positions[0].pending_exit.is_some());
+        // a failed exit can fire again
+        assert_eq!(positions[1].id, failed);
+        assert!(positions[1].pending_exit.is_none());
+    }
+}
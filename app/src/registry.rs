@@ -0,0 +1,44 @@
+use raydium_amm_poc::amm_math::PoolState;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock,
+    },
+};
+
+// pools shared between the watcher tasks
+#[derive(Default)]
+pub struct PoolRegistry {
+    pools: RwLock<HashMap<Pubkey, PoolState>>,
+    new_pools: AtomicUsize,
+}
+
+impl PoolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, amm_pool_key: Pubkey, pool_state: PoolState) {
+        self.pools.write().unwrap().insert(amm_pool_key, pool_state);
+    }
+
+    pub fn pool_keys(&self) -> Vec<Pubkey> {
+        self.pools.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.read().unwrap().len()
+    }
+
+    // the new pool is refreshed with the others from then on
+    pub fn record_new_pool(&self, amm_pool_key: Pubkey, pool_state: PoolState) {
+        self.insert(amm_pool_key, pool_state);
+        self.new_pools.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn new_pool_count(&self) -> usize {
+        self.new_pools.load(Ordering::Relaxed)
+    }
+}
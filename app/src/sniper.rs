@@ -0,0 +1,455 @@
+use anyhow::{format_err, Result};
+use raydium_amm::log::InitLog;
+use raydium_amm_poc::{
+    amm_math::{load_state, load_state_with_amm_info, PoolState, Quote},
+    events::{parse_ray_logs, RayLogEvent},
+    priority_fee::{
+        capped_compute_unit_price, recent_priority_fee, swap_writable_accounts, PriorityFeeConfig,
+    },
+    risk::{build_token_risk_report, RiskPolicy},
+    transaction::{build_swap_instructions, build_unsigned_v0_transaction, SwapTxConfig},
+    utils::AmmKeys,
+};
+use solana_client::{rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::VersionedTransaction,
+};
+use spl_token::native_mint;
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::config::{env_or, env_pubkeys};
+use crate::execution::priority_fee_config_from_env;
+use crate::indexer::fetch_init_pool;
+use crate::status::{StatusBoard, TaskStatus};
+use crate::subscription::{logs_subscribe_with_reconnect, SubscriptionEvent};
+
+pub const SNIPE_TASK: &str = "snipe";
+// the swap is signed this long before open time, so its blockhash is still fresh
+const SIGN_LEAD_TIME: u64 = 20;
+
+#[derive(Clone, Debug)]
+pub struct SnipeFilter {
+    /// mints to buy, empty buys any mint that passes the other checks
+    pub watched_mints: Vec<Pubkey>,
+    /// mint we pay with, the pool must trade it
+    pub pc_mint: Pubkey,
+    /// initial liquidity on the pc side, raw units
+    pub min_pc_liquidity: u64,
+    /// checks on the bought mint, mint authority included
+    pub risk_policy: RiskPolicy,
+}
+
+#[derive(Clone, Debug)]
+pub struct SnipeConfig {
+    pub filter: SnipeFilter,
+    /// in pc_mint units
+    pub amount_in: u64,
+    pub slippage_bps: u64,
+    pub swap_config: SwapTxConfig,
+    /// the price is taken from recent fees when the swap is signed
+    pub priority_fee: PriorityFeeConfig,
+}
+
+impl SnipeConfig {
+    pub fn from_env() -> Result<Self> {
+        Ok(SnipeConfig {
+            filter: SnipeFilter {
+                watched_mints: env_pubkeys("SNIPE_MINTS")?,
+                pc_mint: env_or("SNIPE_PC_MINT", native_mint::id())?,
+                min_pc_liquidity: env_or("SNIPE_MIN_PC_LIQUIDITY", 0)?,
+                risk_policy: RiskPolicy {
+                    require_revoked_mint_authority: env_or("SNIPE_REQUIRE_REVOKED_MINT", true)?,
+                    require_revoked_freeze_authority: env_or("SNIPE_REQUIRE_REVOKED_FREEZE", true)?,
+                    ..RiskPolicy::default()
+                },
+            },
+            amount_in: env_or("SNIPE_AMOUNT_IN", 0)?,
+            slippage_bps: env_or("SNIPE_SLIPPAGE_BPS", 1000)?,
+            swap_config: SwapTxConfig::default(),
+            priority_fee: priority_fee_config_from_env()?,
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// false if shutdown was requested first
+fn sleep_until(unix_time: u64, shutdown: &AtomicBool) -> bool {
+    while unix_now() < unix_time {
+        if shutdown.load(Ordering::Relaxed) {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    !shutdown.load(Ordering::Relaxed)
+}
+
+// the mint the snipe buys given the initial liquidity, or why the pool is skipped
+fn snipe_mint(
+    filter: &SnipeFilter,
+    keys: &AmmKeys,
+    coin_amount: u64,
+    pc_amount: u64,
+) -> Result<Pubkey> {
+    let (target_mint, pc_liquidity) = if keys.amm_pc_mint == filter.pc_mint {
+        (keys.amm_coin_mint, pc_amount)
+    } else if keys.amm_coin_mint == filter.pc_mint {
+        (keys.amm_pc_mint, coin_amount)
+    } else {
+        return Err(format_err!("pool does not trade {}", filter.pc_mint));
+    };
+    if !filter.watched_mints.is_empty() && !filter.watched_mints.contains(&target_mint) {
+        return Err(format_err!("{} is not watched", target_mint));
+    }
+    if pc_liquidity < filter.min_pc_liquidity {
+        return Err(format_err!(
+            "initial liquidity {} below {}",
+            pc_liquidity,
+            filter.min_pc_liquidity
+        ));
+    }
+    Ok(target_mint)
+}
+
+// snipe_mint, then the risk checks on it
+fn snipe_target(
+    client: &RpcClient,
+    filter: &SnipeFilter,
+    pool: &PoolState,
+    init: &InitLog,
+) -> Result<Pubkey> {
+    let target_mint = snipe_mint(
+        filter,
+        &pool.pool_amm_keys,
+        init.coin_amount,
+        init.pc_amount,
+    )?;
+    let report = build_token_risk_report(client, pool, &target_mint, None, &filter.risk_policy)?;
+    if !report.passed() {
+        return Err(format_err!(
+            "{} failed risk checks {:?}",
+            target_mint,
+            report.failed_checks()
+        ));
+    }
+    Ok(target_mint)
+}
+
+// amount_in of the pc mint, quoted on the given state
+fn snipe_swap_instructions(
+    amm_program_key: &Pubkey,
+    config: &SnipeConfig,
+    owner: &Pubkey,
+    pool: &PoolState,
+    compute_unit_price: u64,
+) -> Result<(Vec<Instruction>, Quote)> {
+    build_swap_instructions(
+        amm_program_key,
+        pool,
+        owner,
+        &config.filter.pc_mint,
+        config.amount_in,
+        config.slippage_bps,
+        &SwapTxConfig {
+            compute_unit_price: capped_compute_unit_price(&config.priority_fee, compute_unit_price),
+            ..config.swap_config.clone()
+        },
+    )
+}
+
+// waits for open time, signs with a fresh blockhash and submits the swap.
+// liquidity can still be added or removed before the pool opens, so the quote
+// is taken on the reserves reloaded when the swap is signed. the pool rejects
+// swaps until it opens, so the swap can not be simulated for its compute units
+// and keeps the default limit, only the price is estimated
+fn execute_snipe(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    config: &SnipeConfig,
+    payer: &Keypair,
+    amm_pool: &Pubkey,
+    open_time: u64,
+    shutdown: &AtomicBool,
+) -> Result<Option<Signature>> {
+    println!(
+        "snipe {}: {} in at {}",
+        amm_pool, config.amount_in, open_time
+    );
+    if !sleep_until(open_time.saturating_sub(SIGN_LEAD_TIME), shutdown) {
+        return Ok(None);
+    }
+    let pool = load_state(client, amm_program_key, amm_pool)?;
+    let compute_unit_price = recent_priority_fee(
+        client,
+        &swap_writable_accounts(&pool),
+        config.priority_fee.percentile,
+    )?;
+    let (instructions, quote) = snipe_swap_instructions(
+        amm_program_key,
+        config,
+        &payer.pubkey(),
+        &pool,
+        compute_unit_price,
+    )?;
+    println!(
+        "snipe {}: {} in for at least {} out",
+        amm_pool, quote.amount_in, quote.other_amount_threshold
+    );
+    let unsigned = build_unsigned_v0_transaction(
+        &payer.pubkey(),
+        &instructions,
+        &[],
+        client.get_latest_blockhash()?,
+    )?;
+    let transaction = VersionedTransaction::try_new(unsigned.message, &[payer])?;
+    if !sleep_until(open_time, shutdown) {
+        return Ok(None);
+    }
+    // preflight would run against a bank that may still be before open time
+    let signature = client.send_transaction_with_config(
+        &transaction,
+        RpcSendTransactionConfig {
+            skip_preflight: true,
+            ..RpcSendTransactionConfig::default()
+        },
+    )?;
+    Ok(Some(signature))
+}
+
+fn prepare_snipe(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    config: &SnipeConfig,
+    signature: &str,
+    init: &InitLog,
+) -> Result<(PoolState, u64)> {
+    let amm_pool = fetch_init_pool(client, amm_program_key, &Signature::from_str(signature)?)?;
+    let (pool, amm_info) = load_state_with_amm_info(client, amm_program_key, &amm_pool)?;
+    snipe_target(client, &config.filter, &pool, init)?;
+    Ok((pool, amm_info.state_data.pool_open_time))
+}
+
+// buys every new pool that passes the filter once it opens
+pub fn run_sniper(
+    url: &str,
+    client: Arc<RpcClient>,
+    amm_program_key: &Pubkey,
+    config: &SnipeConfig,
+    payer: Arc<Keypair>,
+    status: &StatusBoard,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
+    if config.amount_in == 0 {
+        return Err(format_err!("SNIPE_AMOUNT_IN must be set"));
+    }
+    let mut seen_pools: HashSet<Pubkey> = HashSet::new();
+    let mut snipes: Vec<thread::JoinHandle<()>> = Vec::new();
+    logs_subscribe_with_reconnect(
+        url,
+        vec![amm_program_key.to_string()],
+        CommitmentConfig::confirmed(),
+        &shutdown,
+        |event| match event {
+            SubscriptionEvent::Message(log_response) => {
+                status.record_event(SNIPE_TASK, log_response.context.slot);
+                let value = log_response.value;
+                if value.err.is_some() {
+                    return;
+                }
+                let init = match parse_ray_logs(&value.logs)
+                    .into_iter()
+                    .find_map(|log| match log {
+                        RayLogEvent::Init(init) => Some(init),
+                        _ => None,
+                    }) {
+                    Some(init) => init,
+                    None => return,
+                };
+                let (pool, open_time) = match prepare_snipe(
+                    &client,
+                    amm_program_key,
+                    config,
+                    &value.signature,
+                    &init,
+                ) {
+                    Ok(prepared) => prepared,
+                    Err(e) => {
+                        println!("snipe skipped {}: {}", value.signature, e);
+                        return;
+                    }
+                };
+                if !seen_pools.insert(pool.pool_amm_keys.amm_pool) {
+                    return;
+                }
+                // finished snipes already printed their outcome
+                snipes.retain(|snipe| !snipe.is_finished());
+                let amm_pool = pool.pool_amm_keys.amm_pool;
+                let (client, amm_program_key, config, payer, shutdown) = (
+                    client.clone(),
+                    *amm_program_key,
+                    config.clone(),
+                    payer.clone(),
+                    shutdown.clone(),
+                );
+                snipes.push(thread::spawn(move || {
+                    match execute_snipe(
+                        &client,
+                        &amm_program_key,
+                        &config,
+                        &payer,
+                        &amm_pool,
+                        open_time,
+                        &shutdown,
+                    ) {
+                        Ok(Some(signature)) => {
+                            println!("snipe sent {} for {}", signature, amm_pool)
+                        }
+                        Ok(None) => {}
+                        Err(e) => println!("snipe failed {}: {}", amm_pool, e),
+                    }
+                }));
+            }
+            SubscriptionEvent::Gap(gap) => {
+                status.record_gap(SNIPE_TASK);
+                println!(
+                    "snipe gap: slots {}..{} may have been missed",
+                    gap.last_seen + 1,
+                    gap.resumed_at
+                );
+            }
+            SubscriptionEvent::Disconnected => {
+                status.set_status(SNIPE_TASK, TaskStatus::Reconnecting);
+            }
+        },
+    )?;
+    for snipe in snipes {
+        let _ = snipe.join();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raydium_amm_poc::{
+        amm_math::{min_amount_with_slippage, swap_exact_amount},
+        fixtures::pool_state,
+        raydium_amm::maths::SwapDirection,
+    };
+    use solana_sdk::compute_budget::ComputeBudgetInstruction;
+    use std::convert::TryInto;
+
+    fn filter(pc_mint: Pubkey) -> SnipeFilter {
+        SnipeFilter {
+            watched_mints: Vec::new(),
+            pc_mint,
+            min_pc_liquidity: 1_000,
+            risk_policy: RiskPolicy::default(),
+        }
+    }
+
+    fn config(pc_mint: Pubkey) -> SnipeConfig {
+        SnipeConfig {
+            filter: filter(pc_mint),
+            amount_in: 10_000,
+            slippage_bps: 100,
+            swap_config: SwapTxConfig::default(),
+            priority_fee: PriorityFeeConfig {
+                max_compute_unit_price: Some(5_000),
+                ..PriorityFeeConfig::default()
+            },
+        }
+    }
+
+    #[test]
+    fn snipe_mint_buys_the_side_opposite_the_pc_mint() {
+        let (coin, pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let keys = pool_state(coin, pc, 0, 0).pool_amm_keys;
+        assert_eq!(snipe_mint(&filter(pc), &keys, 0, 1_000).unwrap(), coin);
+        // pools listed the other way around
+        assert_eq!(snipe_mint(&filter(coin), &keys, 1_000, 0).unwrap(), pc);
+        assert!(snipe_mint(&filter(Pubkey::new_unique()), &keys, 1_000, 1_000).is_err());
+    }
+
+    #[test]
+    fn snipe_mint_checks_the_watched_mints_and_liquidity() {
+        let (coin, pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let keys = pool_state(coin, pc, 0, 0).pool_amm_keys;
+        // only the pc side counts towards the liquidity
+        assert!(snipe_mint(&filter(pc), &keys, 1_000_000, 999).is_err());
+        let watching_other = SnipeFilter {
+            watched_mints: vec![Pubkey::new_unique()],
+            ..filter(pc)
+        };
+        assert!(snipe_mint(&watching_other, &keys, 0, 1_000).is_err());
+        let watching_coin = SnipeFilter {
+            watched_mints: vec![coin],
+            ..filter(pc)
+        };
+        assert_eq!(snipe_mint(&watching_coin, &keys, 0, 1_000).unwrap(), coin);
+    }
+
+    #[test]
+    fn snipe_swap_instructions_quote_the_given_reserves() {
+        let (coin, pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let amm_program = Pubkey::new_unique();
+        let config = config(pc);
+        let pool = pool_state(coin, pc, 1_000_000, 2_000_000);
+        let (instructions, quote) =
+            snipe_swap_instructions(&amm_program, &config, &Pubkey::new_unique(), &pool, 9_000)
+                .unwrap();
+        let amount_out = swap_exact_amount(
+            2_000_000,
+            1_000_000,
+            25,
+            10000,
+            SwapDirection::PC2Coin,
+            10_000,
+            true,
+        )
+        .unwrap();
+        assert_eq!(quote.amount_in, 10_000);
+        assert_eq!(
+            quote.other_amount_threshold,
+            min_amount_with_slippage(amount_out, 100)
+        );
+
+        let swap = instructions
+            .iter()
+            .find(|instruction| instruction.program_id == amm_program)
+            .unwrap();
+        let amount =
+            |offset: usize| u64::from_le_bytes(swap.data[offset..offset + 8].try_into().unwrap());
+        assert_eq!(
+            (amount(1), amount(9)),
+            (10_000, quote.other_amount_threshold)
+        );
+        // the estimated price is capped by the config
+        assert!(instructions.contains(&ComputeBudgetInstruction::set_compute_unit_price(5_000)));
+
+        // liquidity added before open time raises the quote
+        let deeper = pool_state(coin, pc, 2_000_000, 4_000_000);
+        let (_, deeper_quote) =
+            snipe_swap_instructions(&amm_program, &config, &Pubkey::new_unique(), &deeper, 0)
+                .unwrap();
+        assert!(deeper_quote.other_amount_threshold > quote.other_amount_threshold);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+#[derive(Clone, Debug)]
+pub enum TaskStatus {
+    Starting,
+    Running,
+    Reconnecting,
+    Stopped,
+    Failed(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct TaskReport {
+    pub status: TaskStatus,
+    pub events: u64,
+    pub gaps: u64,
+    pub last_slot: Option<u64>,
+}
+
+impl Default for TaskReport {
+    fn default() -> Self {
+        TaskReport {
+            status: TaskStatus::Starting,
+            events: 0,
+            gaps: 0,
+            last_slot: None,
+        }
+    }
+}
+
+// per-task health, written by the watchers and printed by the reporter
+#[derive(Default)]
+pub struct StatusBoard {
+    tasks: Mutex<BTreeMap<&'static str, TaskReport>>,
+}
+
+impl StatusBoard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_status(&self, task: &'static str, status: TaskStatus) {
+        self.tasks.lock().unwrap().entry(task).or_default().status = status;
+    }
+
+    pub fn record_event(&self, task: &'static str, slot: u64) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let report = tasks.entry(task).or_default();
+        report.status = TaskStatus::Running;
+        report.events += 1;
+        report.last_slot = Some(report.last_slot.map_or(slot, |last| last.max(slot)));
+    }
+
+    pub fn record_gap(&self, task: &'static str) {
+        self.tasks.lock().unwrap().entry(task).or_default().gaps += 1;
+    }
+
+    pub fn snapshot(&self) -> Vec<(&'static str, TaskReport)> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(task, report)| (*task, report.clone()))
+            .collect()
+    }
+
+    pub fn print(&self) {
+        for (task, report) in self.snapshot() {
+            println!(
+                "[{}] {:?} events={} gaps={} last_slot={:?}",
+                task, report.status, report.events, report.gaps, report.last_slot
+            );
+        }
+    }
+}
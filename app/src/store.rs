@@ -0,0 +1,196 @@
+use anyhow::Result;
+use raydium_amm_poc::events::AmmEvent;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::convert::TryFrom;
+
+// candle intervals in seconds: 1m, 5m, 1h
+pub const CANDLE_INTERVALS: [i64; 3] = [60, 300, 3600];
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    signature TEXT NOT NULL,
+    ix_index INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    block_time INTEGER,
+    pool TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    direction INTEGER,
+    coin_amount INTEGER NOT NULL,
+    pc_amount INTEGER NOT NULL,
+    price REAL,
+    PRIMARY KEY (signature, ix_index)
+);
+CREATE INDEX IF NOT EXISTS events_pool_slot ON events (pool, slot);
+CREATE TABLE IF NOT EXISTS candles (
+    pool TEXT NOT NULL,
+    interval INTEGER NOT NULL,
+    open_time INTEGER NOT NULL,
+    open REAL NOT NULL,
+    high REAL NOT NULL,
+    low REAL NOT NULL,
+    close REAL NOT NULL,
+    volume_coin REAL NOT NULL,
+    volume_pc REAL NOT NULL,
+    trades INTEGER NOT NULL,
+    first_slot INTEGER NOT NULL,
+    last_slot INTEGER NOT NULL,
+    PRIMARY KEY (pool, interval, open_time)
+);
+CREATE TABLE IF NOT EXISTS backfill_cursor (
+    pool TEXT PRIMARY KEY,
+    oldest TEXT,
+    newest TEXT
+);
+CREATE TABLE IF NOT EXISTS skipped_transactions (
+    signature TEXT PRIMARY KEY,
+    pool TEXT,
+    slot INTEGER NOT NULL,
+    reason TEXT NOT NULL
+);
+";
+
+// open and close follow slot order, so live and backfilled events can be
+// written in any order
+const UPSERT_CANDLE: &str = "
+INSERT INTO candles (pool, interval, open_time, open, high, low, close, volume_coin, volume_pc, trades, first_slot, last_slot)
+VALUES (?1, ?2, ?3, ?4, ?4, ?4, ?4, ?5, ?6, 1, ?7, ?7)
+ON CONFLICT (pool, interval, open_time) DO UPDATE SET
+    open = CASE WHEN excluded.first_slot < candles.first_slot THEN excluded.open ELSE candles.open END,
+    close = CASE WHEN excluded.last_slot >= candles.last_slot THEN excluded.close ELSE candles.close END,
+    high = MAX(candles.high, excluded.high),
+    low = MIN(candles.low, excluded.low),
+    volume_coin = candles.volume_coin + excluded.volume_coin,
+    volume_pc = candles.volume_pc + excluded.volume_pc,
+    trades = candles.trades + 1,
+    first_slot = MIN(candles.first_slot, excluded.first_slot),
+    last_slot = MAX(candles.last_slot, excluded.last_slot)
+";
+
+// the processed signatures of a pool backfill are every one from oldest to newest
+#[derive(Clone, Debug, Default)]
+pub struct BackfillCursor {
+    /// older history resumes below it
+    pub oldest: Option<String>,
+    /// signatures above it arrived after the last run
+    pub newest: Option<String>,
+}
+
+fn to_sql_int(amount: u64) -> i64 {
+    i64::try_from(amount).unwrap_or(i64::MAX)
+}
+
+pub struct EventStore {
+    conn: Connection,
+}
+
+impl EventStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(EventStore { conn })
+    }
+
+    pub fn contains_signature(&self, signature: &str) -> Result<bool> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT 1 FROM events WHERE signature = ?1 LIMIT 1",
+                params![signature],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    pub fn backfill_cursor(&self, pool: &str) -> Result<BackfillCursor> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT oldest, newest FROM backfill_cursor WHERE pool = ?1",
+                params![pool],
+                |row| {
+                    Ok(BackfillCursor {
+                        oldest: row.get(0)?,
+                        newest: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?
+            .unwrap_or_default())
+    }
+
+    pub fn set_backfill_oldest(&self, pool: &str, signature: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO backfill_cursor (pool, oldest) VALUES (?1, ?2)
+             ON CONFLICT (pool) DO UPDATE SET oldest = excluded.oldest",
+            params![pool, signature],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_backfill_newest(&self, pool: &str, signature: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO backfill_cursor (pool, newest) VALUES (?1, ?2)
+             ON CONFLICT (pool) DO UPDATE SET newest = excluded.newest",
+            params![pool, signature],
+        )?;
+        Ok(())
+    }
+
+    // transactions that could not be indexed, kept for inspection
+    pub fn record_skipped_transaction(
+        &self,
+        signature: &str,
+        pool: Option<&str>,
+        slot: u64,
+        reason: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO skipped_transactions (signature, pool, slot, reason)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![signature, pool, to_sql_int(slot), reason],
+        )?;
+        Ok(())
+    }
+
+    // returns false if the event was already stored
+    pub fn insert_event(&mut self, event: &AmmEvent, price: Option<f64>) -> Result<bool> {
+        let tx = self.conn.transaction()?;
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO events (signature, ix_index, slot, block_time, pool, kind, direction, coin_amount, pc_amount, price)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                event.signature,
+                event.index,
+                to_sql_int(event.slot),
+                event.block_time,
+                event.pool.to_string(),
+                event.kind.as_str(),
+                event.swap_direction.map(|direction| direction as u64 as i64),
+                to_sql_int(event.coin_amount),
+                to_sql_int(event.pc_amount),
+                price,
+            ],
+        )? > 0;
+        if let (true, Some(_), Some(block_time), Some(price)) =
+            (inserted, event.swap_direction, event.block_time, price)
+        {
+            for interval in CANDLE_INTERVALS.iter() {
+                tx.execute(
+                    UPSERT_CANDLE,
+                    params![
+                        event.pool.to_string(),
+                        interval,
+                        block_time - block_time.rem_euclid(*interval),
+                        price,
+                        event.coin_amount as f64,
+                        event.pc_amount as f64,
+                        to_sql_int(event.slot),
+                    ],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(inserted)
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::Result;
+use backoff::{backoff::Backoff, ExponentialBackoff};
+use crossbeam_channel::Receiver;
+use solana_client::{
+    pubsub_client::{PubsubClient, PubsubClientError},
+    rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter},
+    rpc_response::{Response, RpcLogsResponse, SlotInfo},
+};
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+// how often a blocked receiver wakes up to check for shutdown
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug)]
+pub struct SlotGap {
+    /// last slot seen before the gap
+    pub last_seen: u64,
+    /// first slot seen after the gap
+    pub resumed_at: u64,
+}
+
+impl SlotGap {
+    pub fn missed_slots(&self) -> u64 {
+        self.resumed_at.saturating_sub(self.last_seen).saturating_sub(1)
+    }
+}
+
+#[derive(Debug)]
+pub enum SubscriptionEvent<T> {
+    Message(T),
+    Gap(SlotGap),
+    Disconnected,
+}
+
+// never give up: a watcher should keep retrying for as long as the process runs
+pub fn reconnect_backoff() -> ExponentialBackoff {
+    ExponentialBackoff {
+        initial_interval: Duration::from_millis(500),
+        max_interval: Duration::from_secs(30),
+        max_elapsed_time: None,
+        ..Default::default()
+    }
+}
+
+// waits in short naps, false if shutdown came first
+pub fn wait_unless_shutdown(wait: Duration, shutdown: &AtomicBool) -> bool {
+    let deadline = Instant::now() + wait;
+    while !shutdown.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep((deadline - now).min(SHUTDOWN_POLL_INTERVAL));
+    }
+    false
+}
+
+// subscribes again whenever the connection fails or the stream closes. the
+// backoff only resets once a stream delivered a message, so a node that
+// accepts and drops the socket right away is retried slower and slower
+fn subscribe_with_reconnect<R, T, S, F>(
+    url: &str,
+    shutdown: &AtomicBool,
+    mut subscribe: S,
+    mut on_stream: F,
+) -> Result<()>
+where
+    S: FnMut() -> Result<(R, Receiver<T>), PubsubClientError>,
+    F: FnMut(Receiver<T>) -> bool,
+{
+    let mut backoff = reconnect_backoff();
+    while !shutdown.load(Ordering::Relaxed) {
+        match subscribe() {
+            Ok((_subscription, receiver)) => {
+                if on_stream(receiver) {
+                    backoff.reset();
+                }
+            }
+            Err(e) => println!("subscribe to {} failed: {}", url, e),
+        }
+        if shutdown.load(Ordering::Relaxed) {
+            break;
+        }
+        let wait = backoff
+            .next_backoff()
+            .unwrap_or_else(|| Duration::from_secs(30));
+        println!("resubscribing to {} in {:?}", url, wait);
+        if !wait_unless_shutdown(wait, shutdown) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// returns once the sender side is gone or shutdown is requested, true if any
+// message arrived
+fn receive_until_closed<T, F>(
+    receiver: Receiver<T>,
+    shutdown: &AtomicBool,
+    mut on_message: F,
+) -> bool
+where
+    F: FnMut(T),
+{
+    let mut received = false;
+    while !shutdown.load(Ordering::Relaxed) {
+        match receiver.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+            Ok(message) => {
+                received = true;
+                on_message(message)
+            }
+            Err(e) if e.is_timeout() => continue,
+            Err(_) => break,
+        }
+    }
+    received
+}
+
+fn check_gap(last_seen: Option<u64>, resumed_at: u64) -> Option<SlotGap> {
+    match last_seen {
+        Some(last_seen) if resumed_at > last_seen + 1 => Some(SlotGap {
+            last_seen,
+            resumed_at,
+        }),
+        _ => None,
+    }
+}
+
+// logs only arrive for slots that touch the filter, so a gap is only reported
+// across a reconnect, never between two consecutive notifications
+pub fn logs_subscribe_with_reconnect<F>(
+    url: &str,
+    addresses: Vec<String>,
+    commitment: CommitmentConfig,
+    shutdown: &AtomicBool,
+    mut on_event: F,
+) -> Result<()>
+where
+    F: FnMut(SubscriptionEvent<Response<RpcLogsResponse>>),
+{
+    let mut last_slot: Option<u64> = None;
+    subscribe_with_reconnect(
+        url,
+        shutdown,
+        || {
+            PubsubClient::logs_subscribe(
+                url,
+                RpcTransactionLogsFilter::Mentions(addresses.clone()),
+                RpcTransactionLogsConfig {
+                    commitment: Some(commitment),
+                },
+            )
+        },
+        |log_receiver| {
+            let mut resumed = last_slot.is_some();
+            let received = receive_until_closed(log_receiver, shutdown, |log_response| {
+                let slot = log_response.context.slot;
+                if resumed {
+                    if let Some(gap) = check_gap(last_slot, slot) {
+                        on_event(SubscriptionEvent::Gap(gap));
+                    }
+                    resumed = false;
+                }
+                last_slot = Some(last_slot.map_or(slot, |last| last.max(slot)));
+                on_event(SubscriptionEvent::Message(log_response));
+            });
+            if !shutdown.load(Ordering::Relaxed) {
+                println!("logs subscription to {} closed", url);
+                on_event(SubscriptionEvent::Disconnected);
+            }
+            received
+        },
+    )
+}
+
+// a slot notification carries its parent, so skipped leader slots are not
+// reported, only notifications we did not receive
+pub fn slot_subscribe_with_reconnect<F>(
+    url: &str,
+    shutdown: &AtomicBool,
+    mut on_event: F,
+) -> Result<()>
+where
+    F: FnMut(SubscriptionEvent<SlotInfo>),
+{
+    let mut last_slot: Option<u64> = None;
+    subscribe_with_reconnect(
+        url,
+        shutdown,
+        || PubsubClient::slot_subscribe(url),
+        |slot_receiver| {
+            let received = receive_until_closed(slot_receiver, shutdown, |slot_info| {
+                match last_slot {
+                    Some(last_seen) if slot_info.parent > last_seen => {
+                        on_event(SubscriptionEvent::Gap(SlotGap {
+                            last_seen,
+                            resumed_at: slot_info.slot,
+                        }))
+                    }
+                    _ => {}
+                }
+                last_slot = Some(last_slot.map_or(slot_info.slot, |last| last.max(slot_info.slot)));
+                on_event(SubscriptionEvent::Message(slot_info));
+            });
+            if !shutdown.load(Ordering::Relaxed) {
+                println!("slot subscription to {} closed", url);
+                on_event(SubscriptionEvent::Disconnected);
+            }
+            received
+        },
+    )
+}
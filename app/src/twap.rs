@@ -0,0 +1,279 @@
+use anyhow::{format_err, Result};
+use rand::Rng;
+use raydium_amm_poc::{
+    amm_math::{calc_price_impact_bps, calc_swap_fee, load_state, swap_exact_amount, PoolState},
+    priority_fee::PriorityFeeConfig,
+    raydium_amm::maths::SwapDirection,
+    split::pool_swap_direction,
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::execution::{build_signed_swap_transaction, simulate_swap};
+
+#[derive(Clone, Debug)]
+pub struct TwapConfig {
+    pub amm_pool: Pubkey,
+    pub input_mint: Pubkey,
+    /// raw units of input_mint, split evenly across the children
+    pub total_amount: u64,
+    pub children: u64,
+    pub duration: Duration,
+    /// each child moves up to this share of the interval earlier or later, 0 keeps a fixed grid
+    pub jitter_bps: u64,
+    /// children quoted above this impact are skipped
+    pub max_price_impact_bps: u64,
+    pub slippage_bps: u64,
+    pub priority_fee: PriorityFeeConfig,
+    /// simulate the children instead of sending them
+    pub dry_run: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TwapFill {
+    pub child: u64,
+    pub amount_in: u64,
+    /// quoted by swap_exact_amount before the child was sent
+    pub expected_amount_out: u64,
+    pub min_amount_out: u64,
+    pub price_impact_bps: u64,
+    /// none in dry run
+    pub signature: Option<String>,
+}
+
+// child amounts, the remainder goes to the last one
+fn child_amounts(total_amount: u64, children: u64) -> Vec<u64> {
+    let amount = total_amount / children;
+    let mut amounts = vec![amount; children as usize];
+    if let Some(last) = amounts.last_mut() {
+        *last += total_amount - amount * children;
+    }
+    amounts
+}
+
+// offsets from the start, the first child goes out immediately
+fn child_offsets(config: &TwapConfig) -> Vec<Duration> {
+    let interval = config.duration.as_secs_f64() / config.children as f64;
+    let jitter = interval * config.jitter_bps as f64 / 10000.0;
+    let mut rng = rand::thread_rng();
+    (0..config.children)
+        .map(|child| {
+            let offset = if child == 0 || jitter <= 0.0 {
+                interval * child as f64
+            } else {
+                interval * child as f64 + rng.gen_range(-jitter..=jitter)
+            };
+            Duration::from_secs_f64(offset.max(0.0))
+        })
+        .collect()
+}
+
+// false if shutdown was requested first
+fn sleep_until(deadline: Instant, shutdown: &AtomicBool) -> bool {
+    while Instant::now() < deadline {
+        if shutdown.load(Ordering::Relaxed) {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(200).min(deadline - Instant::now()));
+    }
+    !shutdown.load(Ordering::Relaxed)
+}
+
+// (amount out, price impact bps) of a base in swap on the current reserves
+fn quote_child(
+    pool: &PoolState,
+    swap_direction: SwapDirection,
+    amount_in: u64,
+) -> Result<(u64, u64)> {
+    let state = &pool.pool;
+    let amount_out = swap_exact_amount(
+        state.pool_pc_vault_amount,
+        state.pool_coin_vault_amount,
+        state.swap_fee_numerator,
+        state.swap_fee_denominator,
+        swap_direction,
+        amount_in,
+        true,
+    )?;
+    let (reserve_in, reserve_out) = match swap_direction {
+        SwapDirection::Coin2PC => (state.pool_coin_vault_amount, state.pool_pc_vault_amount),
+        SwapDirection::PC2Coin => (state.pool_pc_vault_amount, state.pool_coin_vault_amount),
+    };
+    let price_impact_bps = calc_price_impact_bps(
+        reserve_in,
+        reserve_out,
+        amount_in - calc_swap_fee(state, amount_in),
+        amount_out,
+    );
+    Ok((amount_out, price_impact_bps))
+}
+
+// runs the children on schedule and returns the ones that filled. a dry run
+// simulates for owner and needs no payer
+pub fn run_twap(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    config: &TwapConfig,
+    owner: &Pubkey,
+    payer: Option<&Keypair>,
+    shutdown: &AtomicBool,
+) -> Result<Vec<TwapFill>> {
+    if !config.dry_run && payer.is_none() {
+        return Err(format_err!("a live twap needs a payer"));
+    }
+    if config.children == 0 || config.total_amount < config.children {
+        return Err(format_err!(
+            "can not split {} into {} children",
+            config.total_amount,
+            config.children
+        ));
+    }
+    if config.jitter_bps > 10000 {
+        return Err(format_err!(
+            "jitter {} bps is more than the whole interval",
+            config.jitter_bps
+        ));
+    }
+    let start = Instant::now();
+    let mut fills: Vec<TwapFill> = Vec::new();
+    for (child, (amount_in, offset)) in child_amounts(config.total_amount, config.children)
+        .into_iter()
+        .zip(child_offsets(config))
+        .enumerate()
+    {
+        let child = child as u64 + 1;
+        if !sleep_until(start + offset, shutdown) {
+            println!("twap stopped before child {}", child);
+            break;
+        }
+        // reserves move between children, so every child is quoted on a fresh state
+        let pool = match load_state(client, amm_program_key, &config.amm_pool) {
+            Ok(pool) => pool,
+            Err(e) => {
+                println!("twap child {} skipped, load pool failed: {}", child, e);
+                continue;
+            }
+        };
+        let quote = pool_swap_direction(&pool, &config.input_mint)
+            .and_then(|swap_direction| quote_child(&pool, swap_direction, amount_in));
+        let (expected_amount_out, price_impact_bps) = match quote {
+            Ok(quote) => quote,
+            Err(e) => {
+                println!("twap child {} skipped, quote failed: {}", child, e);
+                continue;
+            }
+        };
+        if price_impact_bps > config.max_price_impact_bps {
+            println!(
+                "twap child {} skipped, impact {} bps above {}",
+                child, price_impact_bps, config.max_price_impact_bps
+            );
+            continue;
+        }
+        let result = match payer {
+            Some(payer) if !config.dry_run => build_signed_swap_transaction(
+                client,
+                amm_program_key,
+                &pool,
+                payer,
+                &config.input_mint,
+                amount_in,
+                config.slippage_bps,
+                &config.priority_fee,
+            )
+            .and_then(|(transaction, min_amount_out)| {
+                let signature = client.send_and_confirm_transaction(&transaction)?;
+                Ok((min_amount_out, Some(signature.to_string())))
+            }),
+            _ => simulate_swap(
+                client,
+                amm_program_key,
+                &pool,
+                owner,
+                &config.input_mint,
+                amount_in,
+                config.slippage_bps,
+            )
+            .map(|min_amount_out| (min_amount_out, None)),
+        };
+        match result {
+            Ok((min_amount_out, signature)) => {
+                let fill = TwapFill {
+                    child,
+                    amount_in,
+                    expected_amount_out,
+                    min_amount_out,
+                    price_impact_bps,
+                    signature,
+                };
+                println!("twap fill {}/{}: {:?}", child, config.children, fill);
+                fills.push(fill);
+            }
+            Err(e) => println!("twap child {} failed: {}", child, e),
+        }
+    }
+    let amount_in: u64 = fills.iter().map(|fill| fill.amount_in).sum();
+    let amount_out: u64 = fills.iter().map(|fill| fill.expected_amount_out).sum();
+    println!(
+        "twap done: {}/{} children, {} of {} in, about {} out",
+        fills.len(),
+        config.children,
+        amount_in,
+        config.total_amount,
+        amount_out
+    );
+    Ok(fills)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(children: u64, jitter_bps: u64) -> TwapConfig {
+        TwapConfig {
+            amm_pool: Pubkey::new_unique(),
+            input_mint: Pubkey::new_unique(),
+            total_amount: 1_000,
+            children,
+            duration: Duration::from_secs(100),
+            jitter_bps,
+            max_price_impact_bps: 100,
+            slippage_bps: 100,
+            priority_fee: PriorityFeeConfig::default(),
+            dry_run: true,
+        }
+    }
+
+    #[test]
+    fn child_amounts_gives_the_remainder_to_the_last_child() {
+        assert_eq!(child_amounts(1_000, 3), vec![333, 333, 334]);
+        assert_eq!(child_amounts(1_000, 4), vec![250, 250, 250, 250]);
+        assert_eq!(child_amounts(5, 5), vec![1, 1, 1, 1, 1]);
+        assert_eq!(child_amounts(7, 1), vec![7]);
+    }
+
+    #[test]
+    fn child_offsets_without_jitter_is_a_fixed_grid() {
+        let offsets = child_offsets(&config(4, 0));
+        let secs: Vec<u64> = offsets.iter().map(Duration::as_secs).collect();
+        assert_eq!(secs, vec![0, 25, 50, 75]);
+    }
+
+    #[test]
+    fn child_offsets_stays_within_the_jitter() {
+        for _ in 0..100 {
+            let offsets = child_offsets(&config(4, 5000));
+            assert_eq!(offsets[0], Duration::from_secs(0));
+            for (child, offset) in offsets.iter().enumerate().skip(1) {
+                let grid = 25.0 * child as f64;
+                let offset = offset.as_secs_f64();
+                assert!(offset >= grid - 12.5 - 1e-9 && offset <= grid + 12.5 + 1e-9);
+            }
+        }
+    }
+}
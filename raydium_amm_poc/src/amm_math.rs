@@ -1,11 +1,11 @@
 use crate::raydium_amm::maths::{Calculator, SwapDirection};
 use crate::raydium_amm::processor::Processor;
 use crate::raydium_amm::state::AmmInfo;
-use anyhow::Result;
+use anyhow::{format_err, Result};
 use arrayref::array_ref;
 use raydium_amm::{
     log::decode_ray_log,
-    math::{CheckedCeilDiv, U128},
+    math::{CheckedCeilDiv, U128, U256},
     processor,
     state::{AmmStatus, TargetOrders},
 };
@@ -19,12 +19,11 @@ use solana_sdk::{
     commitment_config::CommitmentConfig, message::Message, pubkey::Pubkey, transaction::Transaction,
 };
 use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
-use spl_token::{
-    state::{Account, Mint},
-    ID,
-};
+use spl_token::state::Account;
+use spl_token_2022::{extension::StateWithExtensions, state::Mint};
+use std::mem::size_of;
 
-use crate::rpc::{get_account, get_multiple_accounts, simulate_transaction};
+use crate::rpc::{deserialize_account, get_multiple_accounts, simulate_transaction};
 use crate::utils::load_amm_keys;
 use crate::{
     instruction::swap,
@@ -45,6 +44,28 @@ pub struct CalculateResult {
     pub swap_fee_denominator: u64,
 }
 
+#[derive(Clone, Debug)]
+pub struct Quote {
+    pub swap_direction: SwapDirection,
+    pub swap_base_in: bool,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    /// swap fee charged in the input token, already included in amount_in
+    pub fee_amount: u64,
+    /// pc per coin before the swap
+    pub spot_price_before: f64,
+    /// pc per coin after the swap
+    pub spot_price_after: f64,
+    /// pc per coin actually paid or received, fee included
+    pub execution_price: f64,
+    /// move of the curve price against the trader, fee excluded
+    pub price_impact_bps: u64,
+    /// min out for swap base in, max in for swap base out
+    pub other_amount_threshold: u64,
+}
+
+// only loaded for pools whose vaults the spl token program owns, so user
+// token accounts of both mints are spl token atas
 #[derive(Clone, Debug)]
 pub struct PoolState {
     pub pool: CalculateResult,
@@ -52,16 +73,13 @@ pub struct PoolState {
     pub pool_market_keys: MarketPubkeys,
 }
 
-// pool_vault_amount = vault_amount + open_orders.native_total + partial filled without consumed - amm.need_take
-pub fn calc_pool_valut_amounts(
-    client: &RpcClient,
-    amm_program_key: &Pubkey,
+// accounts needed by calc_pool_valut_amounts_from_accounts, in this order
+pub fn pool_valut_amounts_keys(
     amm_pool_key: &Pubkey,
     amm_keys: &AmmKeys,
     market_keys: &MarketPubkeys,
-    amm: &AmmInfo,
-) -> Result<CalculateResult> {
-    let load_pubkeys: Vec<Pubkey> = vec![
+) -> Vec<Pubkey> {
+    vec![
         *amm_pool_key,
         amm_keys.amm_target,
         amm_keys.amm_pc_vault,
@@ -69,26 +87,64 @@ pub fn calc_pool_valut_amounts(
         amm_keys.amm_open_order,
         amm_keys.market,
         *market_keys.event_q,
-    ];
+    ]
+}
+
+// pool_vault_amount = vault_amount + open_orders.native_total + partial filled without consumed - amm.need_take
+pub fn calc_pool_valut_amounts(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    amm_pool_key: &Pubkey,
+    amm_keys: &AmmKeys,
+    market_keys: &MarketPubkeys,
+    amm: &AmmInfo,
+) -> Result<CalculateResult> {
+    let load_pubkeys: Vec<Pubkey> = pool_valut_amounts_keys(amm_pool_key, amm_keys, market_keys);
     let rsps = get_multiple_accounts(client, &load_pubkeys)?;
+    calc_pool_valut_amounts_from_accounts(amm_program_key, market_keys, amm, &rsps)
+}
+
+pub fn calc_pool_valut_amounts_from_accounts(
+    amm_program_key: &Pubkey,
+    market_keys: &MarketPubkeys,
+    amm: &AmmInfo,
+    rsps: &[Option<solana_sdk::account::Account>],
+) -> Result<CalculateResult> {
+    if rsps.len() < 7 {
+        return Err(format_err!("expected 7 pool accounts, got {}", rsps.len()));
+    }
     let accounts = array_ref![rsps, 0, 7];
     let [_, amm_target_account, amm_pc_vault_account, amm_coin_vault_account, amm_open_orders_account, market_account, market_event_q_account] =
         accounts;
+    let existing = |account: &Option<solana_sdk::account::Account>, name: &str| {
+        account
+            .clone()
+            .ok_or_else(|| format_err!("pool {} account not found", name))
+    };
     let _amm_target: TargetOrders = transmute_one_pedantic::<TargetOrders>(transmute_to_bytes(
-        &amm_target_account.as_ref().unwrap().clone().data,
+        &existing(amm_target_account, "target orders")?.data,
     ))
     .map_err(|e| e.without_src())?;
-    let amm_pc_vault =
-        Account::unpack(&amm_pc_vault_account.as_ref().unwrap().clone().data).unwrap();
-    let amm_coin_vault =
-        Account::unpack(&amm_coin_vault_account.as_ref().unwrap().clone().data).unwrap();
+    let amm_pc_vault_account = existing(amm_pc_vault_account, "pc vault")?;
+    let amm_coin_vault_account = existing(amm_coin_vault_account, "coin vault")?;
+    // raydium v4 only invokes the spl token program, token-2022 vaults can never be swapped
+    for vault in [&amm_pc_vault_account, &amm_coin_vault_account].iter() {
+        if vault.owner != spl_token::ID {
+            return Err(format_err!(
+                "pool vault is owned by {}, raydium v4 can not swap it",
+                vault.owner
+            ));
+        }
+    }
+    let amm_pc_vault = Account::unpack(&amm_pc_vault_account.data)?;
+    let amm_coin_vault = Account::unpack(&amm_coin_vault_account.data)?;
     let (amm_pool_pc_vault_amount, amm_pool_coin_vault_amount) = if AmmStatus::from_u64(amm.status)
         .orderbook_permission()
     {
         // println!("AMM + OpenBook");
-        let amm_open_orders_account = &mut amm_open_orders_account.as_ref().unwrap().clone();
-        let market_account = &mut market_account.as_ref().unwrap().clone();
-        let market_event_q_account = &mut market_event_q_account.as_ref().unwrap().clone();
+        let amm_open_orders_account = &mut existing(amm_open_orders_account, "open orders")?;
+        let market_account = &mut existing(market_account, "market")?;
+        let market_event_q_account = &mut existing(market_event_q_account, "event queue")?;
 
         let amm_open_orders_info = (&amm.open_orders, amm_open_orders_account).into_account_info();
         let market_account_info = (&amm.market, market_account).into_account_info();
@@ -171,6 +227,16 @@ pub fn calc_swap_token_amount_base_in(
     Ok(swap_amount_out)
 }
 
+pub fn calc_swap_fee(state: &CalculateResult, amount_specified: u64) -> u64 {
+    U128::from(amount_specified)
+        .checked_mul(state.swap_fee_numerator.into())
+        .unwrap()
+        .checked_ceil_div(state.swap_fee_denominator.into())
+        .unwrap()
+        .0
+        .as_u64()
+}
+
 pub fn simulate_calc_swap_token_amount(
     client: &RpcClient,
     amm_program: &Pubkey,
@@ -183,13 +249,13 @@ pub fn simulate_calc_swap_token_amount(
     other_amount_threshold: u64,
     swap_base_in: bool,
 ) -> Result<()> {
+    // load_state only accepts spl token vaults, so both atas are spl token accounts
     let create_pc_ata_ix: solana_sdk::instruction::Instruction =
         create_associated_token_account_idempotent(
             &user_owner,
             &user_owner,
             &*market_keys.pc_mint,
-            // &Pubkey::from_str(token_program_id)?,
-            &ID,
+            &spl_token::ID,
         );
 
     let create_coin_ata_ix: solana_sdk::instruction::Instruction =
@@ -197,8 +263,7 @@ pub fn simulate_calc_swap_token_amount(
             &user_owner,
             &user_owner,
             &*&market_keys.coin_mint,
-            // &Pubkey::from_str(token_program_id)?,
-            &ID,
+            &spl_token::ID,
         );
 
     let simulate_swap_instruction = swap(
@@ -263,13 +328,49 @@ pub fn calc_swap_token_amount_base_out(
     Ok(swap_in_after_add_fee)
 }
 
+// the amm account must belong to the amm program and have the AmmInfo layout
+pub fn decode_amm_info(
+    amm_program_key: &Pubkey,
+    amm_pool_key: &Pubkey,
+    account: &solana_sdk::account::Account,
+) -> Result<AmmInfo> {
+    if account.owner != *amm_program_key {
+        return Err(format_err!(
+            "{} is owned by {}, not the amm program",
+            amm_pool_key,
+            account.owner
+        ));
+    }
+    if account.data.len() != size_of::<AmmInfo>() {
+        return Err(format_err!(
+            "{} is {} bytes, not an amm pool",
+            amm_pool_key,
+            account.data.len()
+        ));
+    }
+    deserialize_account::<AmmInfo>(&account.data)
+}
+
 pub fn load_state(
     client: &RpcClient,
     amm_program_key: &Pubkey,
     amm_pool_key: &Pubkey,
 ) -> Result<PoolState> {
-    let amm_info: AmmInfo = get_account::<AmmInfo>(&client, &amm_pool_key)?.unwrap();
-    let amm_keys: AmmKeys = load_amm_keys(&amm_program_key, &amm_pool_key, &amm_info).unwrap();
+    Ok(load_state_with_amm_info(client, amm_program_key, amm_pool_key)?.0)
+}
+
+// the pool state and the AmmInfo it was loaded from
+pub fn load_state_with_amm_info(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    amm_pool_key: &Pubkey,
+) -> Result<(PoolState, AmmInfo)> {
+    let account = client
+        .get_account_with_commitment(amm_pool_key, CommitmentConfig::processed())?
+        .value
+        .ok_or_else(|| format_err!("amm pool {} not found", amm_pool_key))?;
+    let amm_info: AmmInfo = decode_amm_info(amm_program_key, amm_pool_key, &account)?;
+    let amm_keys: AmmKeys = load_amm_keys(&amm_program_key, &amm_pool_key, &amm_info)?;
     let market_keys: MarketPubkeys =
         get_keys_for_market(&client, &amm_keys.market_program, &amm_keys.market)?;
     let calculate_result: CalculateResult = calc_pool_valut_amounts(
@@ -281,27 +382,35 @@ pub fn load_state(
         &amm_info,
     )?;
 
-    Ok(PoolState {
-        pool: calculate_result,
-        pool_amm_keys: amm_keys,
-        pool_market_keys: market_keys,
-    })
+    Ok((
+        PoolState {
+            pool: calculate_result,
+            pool_amm_keys: amm_keys,
+            pool_market_keys: market_keys,
+        },
+        amm_info,
+    ))
 }
 
+// computed in u128, saturates at u64::MAX
 pub fn max_amount_with_slippage(input_amount: u64, slippage_bps: u64) -> u64 {
-    input_amount
-        .checked_mul(slippage_bps.checked_add(TEN_THOUSAND).unwrap())
-        .unwrap()
-        .checked_div(TEN_THOUSAND)
-        .unwrap()
+    let amount =
+        input_amount as u128 * (TEN_THOUSAND as u128 + slippage_bps as u128) / TEN_THOUSAND as u128;
+    amount.min(u64::MAX as u128) as u64
 }
 
+// computed in u128, slippage above 100% gives 0
 pub fn min_amount_with_slippage(input_amount: u64, slippage_bps: u64) -> u64 {
-    input_amount
-        .checked_mul(TEN_THOUSAND.checked_sub(slippage_bps).unwrap())
-        .unwrap()
-        .checked_div(TEN_THOUSAND)
-        .unwrap()
+    (input_amount as u128 * TEN_THOUSAND.saturating_sub(slippage_bps) as u128
+        / TEN_THOUSAND as u128) as u64
+}
+
+// an amount the pool math produced that a token account can not hold
+fn amount_to_u64(amount: U128) -> Result<u64> {
+    if amount > U128::from(u64::MAX) {
+        return Err(format_err!("amount {} does not fit in u64", amount));
+    }
+    Ok(amount.as_u64())
 }
 
 pub fn swap_exact_amount(
@@ -346,10 +455,9 @@ pub fn swap_exact_amount(
                 .into(),
             )
             .unwrap()
-            .0
-            .as_u64();
+            .0;
 
-        swap_in_after_add_fee
+        amount_to_u64(swap_in_after_add_fee)?
     };
 
     Ok(other_amount_threshold)
@@ -384,14 +492,155 @@ pub fn swap_with_slippage(
     Ok(other_amount_threshold)
 }
 
+pub fn quote_swap(
+    state: &CalculateResult,
+    swap_direction: SwapDirection,
+    amount_specified: u64,
+    swap_base_in: bool,
+    slippage_bps: u64,
+) -> Result<Quote> {
+    let (reserve_in, reserve_out) = match swap_direction {
+        SwapDirection::Coin2PC => (state.pool_coin_vault_amount, state.pool_pc_vault_amount),
+        SwapDirection::PC2Coin => (state.pool_pc_vault_amount, state.pool_coin_vault_amount),
+    };
+    let (amount_in, amount_out, fee_amount) = if swap_base_in {
+        let swap_fee = calc_swap_fee(state, amount_specified);
+        let amount_out = swap_exact_amount(
+            state.pool_pc_vault_amount,
+            state.pool_coin_vault_amount,
+            state.swap_fee_numerator,
+            state.swap_fee_denominator,
+            swap_direction,
+            amount_specified,
+            true,
+        )?;
+        (amount_specified, amount_out, swap_fee)
+    } else {
+        if amount_specified >= reserve_out {
+            return Err(format_err!(
+                "amount out {} exceeds pool reserve {}",
+                amount_specified,
+                reserve_out
+            ));
+        }
+        let swap_in_before_add_fee = amount_to_u64(Calculator::swap_token_amount_base_out(
+            amount_specified.into(),
+            state.pool_pc_vault_amount.into(),
+            state.pool_coin_vault_amount.into(),
+            swap_direction,
+        ))?;
+        let amount_in = swap_exact_amount(
+            state.pool_pc_vault_amount,
+            state.pool_coin_vault_amount,
+            state.swap_fee_numerator,
+            state.swap_fee_denominator,
+            swap_direction,
+            amount_specified,
+            false,
+        )?;
+        (
+            amount_in,
+            amount_specified,
+            amount_in.checked_sub(swap_in_before_add_fee).unwrap(),
+        )
+    };
+    let other_amount_threshold = if swap_base_in {
+        min_amount_with_slippage(amount_out, slippage_bps)
+    } else {
+        max_amount_with_slippage(amount_in, slippage_bps)
+    };
+
+    // the whole input, fee included, stays in the pool
+    let reserve_in_after = reserve_in.checked_add(amount_in).ok_or_else(|| {
+        format_err!(
+            "amount in {} overflows pool reserve {}",
+            amount_in,
+            reserve_in
+        )
+    })?;
+    let reserve_out_after = reserve_out.checked_sub(amount_out).ok_or_else(|| {
+        format_err!(
+            "amount out {} exceeds pool reserve {}",
+            amount_out,
+            reserve_out
+        )
+    })?;
+    let (pc_before, coin_before, pc_after, coin_after, pc_traded, coin_traded) =
+        match swap_direction {
+            SwapDirection::Coin2PC => (
+                reserve_out,
+                reserve_in,
+                reserve_out_after,
+                reserve_in_after,
+                amount_out,
+                amount_in,
+            ),
+            SwapDirection::PC2Coin => (
+                reserve_in,
+                reserve_out,
+                reserve_in_after,
+                reserve_out_after,
+                amount_in,
+                amount_out,
+            ),
+        };
+
+    let price_impact_bps = calc_price_impact_bps(
+        reserve_in,
+        reserve_out,
+        amount_in.checked_sub(fee_amount).unwrap(),
+        amount_out,
+    );
+
+    Ok(Quote {
+        swap_direction,
+        swap_base_in,
+        amount_in,
+        amount_out,
+        fee_amount,
+        spot_price_before: price_in_pc(pc_before, coin_before, state)?,
+        spot_price_after: price_in_pc(pc_after, coin_after, state)?,
+        execution_price: price_in_pc(pc_traded, coin_traded, state)?,
+        price_impact_bps,
+        other_amount_threshold,
+    })
+}
+
+// impact = 1 - (amount_out / amount_in_after_fee) / (reserve_out / reserve_in), rounded up
+pub fn calc_price_impact_bps(
+    reserve_in: u64,
+    reserve_out: u64,
+    amount_in_after_fee: u64,
+    amount_out: u64,
+) -> u64 {
+    if amount_in_after_fee == 0 || reserve_out == 0 {
+        return 0;
+    }
+    let received = U256::from(amount_out)
+        .checked_mul(reserve_in.into())
+        .unwrap()
+        .checked_mul(TEN_THOUSAND.into())
+        .unwrap();
+    let spot = U256::from(amount_in_after_fee)
+        .checked_mul(reserve_out.into())
+        .unwrap();
+    TEN_THOUSAND.saturating_sub(received.checked_div(spot).unwrap().as_u64())
+}
+
+fn price_in_pc(pc_amount: u64, coin_amount: u64, pool: &CalculateResult) -> Result<f64> {
+    if coin_amount == 0 {
+        return Err(format_err!("no coin to price {} pc against", pc_amount));
+    }
+    Ok(
+        (pc_amount as f64) / 10_f64.powf(pool.pool_pc_decimals as f64) / (coin_amount as f64)
+            * 10_f64.powf(pool.pool_coin_decimals as f64),
+    )
+}
+
 pub fn calc_coin_in_pc(pool: &CalculateResult) -> Result<f64> {
     // pc_amount * pc_price = coin_amount * coin_price
     // coin_price = pc_price * (pc_amount / coin_amount)
-
-    Ok((pool.pool_pc_vault_amount as f64)
-        / 10_f64.powf(pool.pool_pc_decimals as f64)
-        / (pool.pool_coin_vault_amount as f64)
-        * 10_f64.powf(pool.pool_coin_decimals as f64))
+    price_in_pc(pool.pool_pc_vault_amount, pool.pool_coin_vault_amount, pool)
 }
 
 pub fn calc_pool_liquidity(pool: &CalculateResult) -> Result<f64> {
@@ -403,9 +652,275 @@ pub fn calc_coin_market_cap(pool_state: &PoolState, client: &RpcClient) -> Resul
     // market_cap = token_price * total_supply
     // let price = calc_coin_in_pc(state).unwrap();
 
-    let account_data = client.get_account_data(&pool_state.pool_amm_keys.amm_coin_mint).unwrap();
-    let mint = Mint::unpack(&account_data).unwrap();
+    let account_data = client.get_account_data(&pool_state.pool_amm_keys.amm_coin_mint)?;
+    calc_coin_supply_from_mint_data(&account_data)
+}
+
+// spl token and token-2022 mints, extensions included
+pub fn calc_coin_supply_from_mint_data(account_data: &[u8]) -> Result<f64> {
+    let mint = StateWithExtensions::<Mint>::unpack(account_data)?.base;
     let supply = (mint.supply as f64) / (10_f64.powf(mint.decimals as f64));
 
     Ok(supply)
 }
+
+#[derive(Clone, Copy, Debug)]
+pub enum ImpactLimit {
+    /// curve price impact, fee excluded, as reported by Quote
+    MaxPriceImpactBps(u64),
+    /// pc per coin the pool may reach after the swap
+    TargetPrice(f64),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MaxAmountsIn {
+    /// largest coin amount to sell for pc
+    pub coin_to_pc: u64,
+    /// largest pc amount to sell for coin
+    pub pc_to_coin: u64,
+}
+
+pub fn calc_max_amounts_in(state: &CalculateResult, limit: ImpactLimit) -> Result<MaxAmountsIn> {
+    Ok(MaxAmountsIn {
+        coin_to_pc: calc_max_amount_in(state, SwapDirection::Coin2PC, limit)?,
+        pc_to_coin: calc_max_amount_in(state, SwapDirection::PC2Coin, limit)?,
+    })
+}
+
+// largest swap base in amount that keeps the pool within the limit
+pub fn calc_max_amount_in(
+    state: &CalculateResult,
+    swap_direction: SwapDirection,
+    limit: ImpactLimit,
+) -> Result<u64> {
+    let (reserve_in, reserve_out) = match swap_direction {
+        SwapDirection::Coin2PC => (state.pool_coin_vault_amount, state.pool_pc_vault_amount),
+        SwapDirection::PC2Coin => (state.pool_pc_vault_amount, state.pool_coin_vault_amount),
+    };
+    if reserve_in == 0 || reserve_out == 0 {
+        return Err(format_err!("pool has no liquidity"));
+    }
+    let fee_numerator = state.swap_fee_numerator as f64;
+    let fee_denominator = state.swap_fee_denominator as f64;
+    let swap = |amount_in: u64| -> Option<(u64, u64)> {
+        reserve_in.checked_add(amount_in)?;
+        let amount_in_after_fee = amount_in.checked_sub(calc_swap_fee(state, amount_in))?;
+        let amount_out = Calculator::swap_token_amount_base_in(
+            amount_in_after_fee.into(),
+            state.pool_pc_vault_amount.into(),
+            state.pool_coin_vault_amount.into(),
+            swap_direction,
+        )
+        .as_u64();
+        Some((amount_in_after_fee, amount_out))
+    };
+
+    match limit {
+        ImpactLimit::MaxPriceImpactBps(max_impact_bps) => {
+            if max_impact_bps >= TEN_THOUSAND {
+                return Err(format_err!("price impact limit must be below 100%"));
+            }
+            // reserve_in / (reserve_in + x) >= 1 - b  =>  x <= reserve_in * b / (1 - b)
+            let max_in_after_fee = U128::from(reserve_in)
+                .checked_mul(max_impact_bps.into())
+                .unwrap()
+                .checked_div((TEN_THOUSAND - max_impact_bps).into())
+                .unwrap();
+            // x = a - a * fee  =>  a = x / (1 - fee)
+            let estimate = max_in_after_fee
+                .checked_mul(state.swap_fee_denominator.into())
+                .unwrap()
+                .checked_div(
+                    (state
+                        .swap_fee_denominator
+                        .checked_sub(state.swap_fee_numerator)
+                        .unwrap())
+                    .into(),
+                )
+                .unwrap();
+            let estimate = if estimate > U128::from(u64::MAX) {
+                u64::MAX
+            } else {
+                estimate.as_u64()
+            };
+            Ok(largest_satisfying(estimate, |amount_in| match swap(amount_in) {
+                Some((amount_in_after_fee, amount_out)) => {
+                    calc_price_impact_bps(reserve_in, reserve_out, amount_in_after_fee, amount_out)
+                        <= max_impact_bps
+                }
+                None => false,
+            }))
+        }
+        ImpactLimit::TargetPrice(target_price) => {
+            if target_price.is_nan() || target_price <= 0.0 {
+                return Err(format_err!("target price must be positive"));
+            }
+            // post trade price in output per input, raw units
+            let pc_per_coin = target_price * 10_f64.powf(state.pool_pc_decimals as f64)
+                / 10_f64.powf(state.pool_coin_decimals as f64);
+            let target = match swap_direction {
+                SwapDirection::Coin2PC => pc_per_coin,
+                SwapDirection::PC2Coin => 1.0 / pc_per_coin,
+            };
+            let within_target = |amount_in: u64| match swap(amount_in) {
+                Some((_, amount_out)) => {
+                    (reserve_out - amount_out) as f64
+                        >= target * (reserve_in as f64 + amount_in as f64)
+                }
+                None => false,
+            };
+            if !within_target(0) {
+                return Ok(0);
+            }
+            // (in + a * (1 - fee)) * (in + a) = in * out / target
+            let fee = fee_numerator / fee_denominator;
+            let (r_in, r_out) = (reserve_in as f64, reserve_out as f64);
+            let a = 1.0 - fee;
+            let b = r_in * (2.0 - fee);
+            let c = r_in * r_in - r_in * r_out / target;
+            let estimate = (-b + (b * b - 4.0 * a * c).sqrt()) / (2.0 * a);
+            let estimate = if estimate.is_finite() && estimate > 0.0 {
+                estimate.min(u64::MAX as f64) as u64
+            } else {
+                0
+            };
+            Ok(largest_satisfying(estimate, within_target))
+        }
+    }
+}
+
+// walks from a closed form estimate to the exact largest amount for which
+// within_limit holds, assuming it holds at 0 and is monotone
+fn largest_satisfying<F>(estimate: u64, within_limit: F) -> u64
+where
+    F: Fn(u64) -> bool,
+{
+    let (mut low, mut high) = if within_limit(estimate) {
+        let mut low = estimate;
+        let mut step = 1u64;
+        loop {
+            let next = low.saturating_add(step);
+            if next == low {
+                return low;
+            }
+            if !within_limit(next) {
+                break (low, next);
+            }
+            low = next;
+            step = step.saturating_mul(2);
+        }
+    } else {
+        let mut high = estimate;
+        let mut step = 1u64;
+        loop {
+            let next = high.saturating_sub(step);
+            if within_limit(next) {
+                break (next, high);
+            }
+            if next == 0 {
+                return 0;
+            }
+            high = next;
+            step = step.saturating_mul(2);
+        }
+    };
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if within_limit(mid) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::pool;
+
+    #[test]
+    fn quote_swap_base_in_charges_fee_and_impact() {
+        let state = pool(1_000_000, 1_000_000);
+        let quote = quote_swap(&state, SwapDirection::Coin2PC, 10_000, true, 50).unwrap();
+        assert_eq!(quote.amount_in, 10_000);
+        assert_eq!(quote.fee_amount, 25);
+        // 1_000_000 * 9_975 / (1_000_000 + 9_975)
+        assert_eq!(quote.amount_out, 9_876);
+        assert_eq!(quote.price_impact_bps, 100);
+        assert_eq!(quote.other_amount_threshold, 9_826);
+        assert_eq!(quote.spot_price_before, 1.0);
+        assert_eq!(quote.execution_price, 0.9876);
+        assert!(quote.spot_price_after < quote.execution_price);
+    }
+
+    #[test]
+    fn quote_swap_base_out_charges_fee_and_impact() {
+        let state = pool(1_000_000, 1_000_000);
+        let quote = quote_swap(&state, SwapDirection::PC2Coin, 9_876, false, 50).unwrap();
+        assert_eq!(quote.amount_out, 9_876);
+        assert_eq!(quote.amount_in, 10_000);
+        assert_eq!(quote.fee_amount, 25);
+        assert_eq!(quote.price_impact_bps, 100);
+        assert_eq!(quote.other_amount_threshold, 10_050);
+    }
+
+    #[test]
+    fn quote_swap_rejects_amounts_that_overflow_the_pool() {
+        let state = pool(1_000_000, 1_000_000);
+        assert!(quote_swap(&state, SwapDirection::Coin2PC, u64::MAX, true, 50).is_err());
+        assert!(quote_swap(&state, SwapDirection::PC2Coin, u64::MAX - 1, true, 50).is_err());
+        // the input for all but one unit of a deep pool does not fit in u64
+        let state = pool(u64::MAX / 2, u64::MAX / 2);
+        assert!(quote_swap(&state, SwapDirection::Coin2PC, u64::MAX / 2 - 1, false, 50).is_err());
+    }
+
+    #[test]
+    fn quote_swap_rejects_base_out_above_reserve() {
+        let state = pool(1_000_000, 1_000_000);
+        assert!(quote_swap(&state, SwapDirection::Coin2PC, 1_000_000, false, 50).is_err());
+    }
+
+    #[test]
+    fn calc_max_amount_in_stops_at_impact_limit() {
+        let state = pool(5_000_000, 2_000_000);
+        for swap_direction in [SwapDirection::Coin2PC, SwapDirection::PC2Coin].iter() {
+            let max_in =
+                calc_max_amount_in(&state, *swap_direction, ImpactLimit::MaxPriceImpactBps(100))
+                    .unwrap();
+            let impact = |amount_in: u64| {
+                quote_swap(&state, *swap_direction, amount_in, true, 0)
+                    .unwrap()
+                    .price_impact_bps
+            };
+            assert!(impact(max_in) <= 100);
+            assert!(impact(max_in + 1) > 100);
+        }
+    }
+
+    #[test]
+    fn calc_max_amount_in_stops_at_target_price() {
+        let state = pool(1_000_000, 1_000_000);
+        let max_in = calc_max_amount_in(
+            &state,
+            SwapDirection::PC2Coin,
+            ImpactLimit::TargetPrice(1.1),
+        )
+        .unwrap();
+        let price_after = |amount_in: u64| {
+            quote_swap(&state, SwapDirection::PC2Coin, amount_in, true, 0)
+                .unwrap()
+                .spot_price_after
+        };
+        assert!(price_after(max_in) <= 1.1);
+        assert!(price_after(max_in + 1) > 1.1);
+    }
+
+    #[test]
+    fn calc_max_amount_in_rejects_full_impact() {
+        let state = pool(1_000_000, 1_000_000);
+        let limit = ImpactLimit::MaxPriceImpactBps(TEN_THOUSAND);
+        assert!(calc_max_amount_in(&state, SwapDirection::Coin2PC, limit).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+use anyhow::{format_err, Result};
+use solana_sdk::{
+    address_lookup_table_account::AddressLookupTableAccount,
+    compute_budget::ComputeBudgetInstruction, hash::Hash, instruction::Instruction, pubkey::Pubkey,
+    transaction::VersionedTransaction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+use spl_token::{native_mint, ID};
+
+use crate::instruction::swap;
+use crate::router::{Route, RouteHop, Router};
+use crate::transaction::build_unsigned_v0_transaction;
+
+// lamports paid per signature on top of the priority fee
+pub const BASE_FEE_LAMPORTS: u64 = 5000;
+
+#[derive(Clone, Debug)]
+pub struct ArbConfig {
+    /// every cycle starts and ends in this mint
+    pub start_mint: Pubkey,
+    /// 2 for pool pairs, 3 for triangles
+    pub max_hops: usize,
+    /// minimum profit after fees, in start mint units
+    pub min_profit: u64,
+    pub compute_unit_limit: u32,
+    /// micro lamports per compute unit
+    pub compute_unit_price: u64,
+}
+
+impl ArbConfig {
+    // in lamports, see priority_fee_in_start_mint
+    pub fn estimated_priority_fee(&self) -> u64 {
+        let priority = (self.compute_unit_limit as u128 * self.compute_unit_price as u128
+            + 999_999)
+            / 1_000_000;
+        BASE_FEE_LAMPORTS + priority as u64
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ArbOpportunity {
+    pub route: Route,
+    /// amount_out - amount_in, pool fees already deducted
+    pub gross_profit: u64,
+    /// in start mint units
+    pub priority_fee: u64,
+    pub net_profit: i64,
+}
+
+// the priority fee in start mint units, priced through the loaded start
+// mint / wsol pool with the most wsol, rounded up
+fn priority_fee_in_start_mint(router: &Router, config: &ArbConfig) -> Result<u64> {
+    let fee = config.estimated_priority_fee();
+    if config.start_mint == native_mint::id() {
+        return Ok(fee);
+    }
+    let (sol_reserve, start_reserve) = router
+        .pools_for_mint(&config.start_mint)
+        .iter()
+        .filter_map(|&pool_index| {
+            let pool = &router.pools()[pool_index];
+            let keys = &pool.pool_amm_keys;
+            if keys.amm_coin_mint == native_mint::id() {
+                Some((
+                    pool.pool.pool_coin_vault_amount,
+                    pool.pool.pool_pc_vault_amount,
+                ))
+            } else if keys.amm_pc_mint == native_mint::id() {
+                Some((
+                    pool.pool.pool_pc_vault_amount,
+                    pool.pool.pool_coin_vault_amount,
+                ))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(sol_reserve, _)| *sol_reserve)
+        .filter(|(sol_reserve, _)| *sol_reserve > 0)
+        .ok_or_else(|| {
+            format_err!(
+                "no loaded wsol pool prices the priority fee in {}",
+                config.start_mint
+            )
+        })?;
+    let fee = (fee as u128 * start_reserve as u128 + sol_reserve as u128 - 1) / sol_reserve as u128;
+    Ok(fee.min(u64::MAX as u128) as u64)
+}
+
+// (pool index, input mint) for every hop of every cycle back to start_mint
+fn find_cycles(router: &Router, start_mint: &Pubkey, max_hops: usize) -> Vec<Vec<(usize, Pubkey)>> {
+    let mut cycles: Vec<Vec<(usize, Pubkey)>> = Vec::new();
+    let mut path: Vec<(usize, Pubkey)> = Vec::new();
+    extend_cycles(
+        router,
+        start_mint,
+        start_mint,
+        max_hops,
+        &mut path,
+        &mut cycles,
+    );
+    cycles
+}
+
+fn extend_cycles(
+    router: &Router,
+    start_mint: &Pubkey,
+    input_mint: &Pubkey,
+    max_hops: usize,
+    path: &mut Vec<(usize, Pubkey)>,
+    cycles: &mut Vec<Vec<(usize, Pubkey)>>,
+) {
+    for &pool_index in router.pools_for_mint(input_mint) {
+        if path.iter().any(|(used, _)| *used == pool_index) {
+            continue;
+        }
+        let keys = &router.pools()[pool_index].pool_amm_keys;
+        let output_mint = if *input_mint == keys.amm_coin_mint {
+            keys.amm_pc_mint
+        } else {
+            keys.amm_coin_mint
+        };
+        path.push((pool_index, *input_mint));
+        if output_mint == *start_mint {
+            if path.len() >= 2 {
+                cycles.push(path.clone());
+            }
+        } else if path.len() < max_hops {
+            extend_cycles(router, start_mint, &output_mint, max_hops, path, cycles);
+        }
+        path.pop();
+    }
+}
+
+// each hop is out = g * r_out * x / (r_in + g * x), and composing two such
+// curves gives another one: A = a1 * a2, B = b1 * b2, C = b2 * c1 + a1 * c2.
+// profit A * x / (B + C * x) - x is maximal at x = (sqrt(A * B) - B) / C
+fn optimal_cycle_input(router: &Router, cycle: &[(usize, Pubkey)]) -> u64 {
+    let (mut a, mut b, mut c) = (1.0f64, 1.0f64, 0.0f64);
+    for (pool_index, input_mint) in cycle.iter() {
+        let pool = &router.pools()[*pool_index];
+        let (reserve_in, reserve_out) = if *input_mint == pool.pool_amm_keys.amm_coin_mint {
+            (
+                pool.pool.pool_coin_vault_amount,
+                pool.pool.pool_pc_vault_amount,
+            )
+        } else {
+            (
+                pool.pool.pool_pc_vault_amount,
+                pool.pool.pool_coin_vault_amount,
+            )
+        };
+        let g = 1.0 - pool.pool.swap_fee_numerator as f64 / pool.pool.swap_fee_denominator as f64;
+        let (hop_a, hop_b, hop_c) = (g * reserve_out as f64, reserve_in as f64, g);
+        c = hop_b * c + a * hop_c;
+        a *= hop_a;
+        b *= hop_b;
+    }
+    if a <= b || c <= 0.0 {
+        return 0;
+    }
+    let optimal = ((a * b).sqrt() - b) / c;
+    if optimal.is_finite() && optimal > 0.0 {
+        optimal.min(u64::MAX as f64) as u64
+    } else {
+        0
+    }
+}
+
+fn simulate_cycle(router: &Router, cycle: &[(usize, Pubkey)], amount_in: u64) -> Option<Route> {
+    let mut hops: Vec<RouteHop> = Vec::new();
+    let mut amount = amount_in;
+    for (pool_index, input_mint) in cycle.iter() {
+        let hop = router.quote_hop(*pool_index, input_mint, amount).ok()?;
+        amount = hop.amount_out;
+        hops.push(hop);
+    }
+    Some(Route {
+        hops,
+        amount_in,
+        amount_out: amount,
+    })
+}
+
+// profitable cycles before priority fee, best net profit first
+pub fn scan_arbitrage(router: &Router, config: &ArbConfig) -> Result<Vec<ArbOpportunity>> {
+    let priority_fee = priority_fee_in_start_mint(router, config)?;
+    let mut opportunities: Vec<ArbOpportunity> = Vec::new();
+    for cycle in find_cycles(router, &config.start_mint, config.max_hops) {
+        let amount_in = optimal_cycle_input(router, &cycle);
+        if amount_in == 0 {
+            continue;
+        }
+        let route = match simulate_cycle(router, &cycle, amount_in) {
+            Some(route) if route.amount_out > route.amount_in => route,
+            _ => continue,
+        };
+        let gross_profit = route.amount_out - route.amount_in;
+        opportunities.push(ArbOpportunity {
+            net_profit: gross_profit as i64 - priority_fee as i64,
+            gross_profit,
+            priority_fee,
+            route,
+        });
+    }
+    opportunities.sort_by(|a, b| b.net_profit.cmp(&a.net_profit));
+    Ok(opportunities)
+}
+
+// intermediate hops must return exactly what the next hop spends, and the last
+// hop must cover amount_in + min_profit + priority_fee, or nothing executes
+pub fn build_arbitrage_instructions(
+    router: &Router,
+    amm_program: &Pubkey,
+    opportunity: &ArbOpportunity,
+    config: &ArbConfig,
+    user_owner: &Pubkey,
+) -> Result<Vec<Instruction>> {
+    let hops = &opportunity.route.hops;
+    if hops.is_empty() {
+        return Err(format_err!("arbitrage route has no hops"));
+    }
+    let mut instructions: Vec<Instruction> = vec![
+        ComputeBudgetInstruction::set_compute_unit_limit(config.compute_unit_limit),
+        ComputeBudgetInstruction::set_compute_unit_price(config.compute_unit_price),
+    ];
+    for hop in hops.iter() {
+        instructions.push(create_associated_token_account_idempotent(
+            user_owner,
+            user_owner,
+            &hop.output_mint,
+            &ID,
+        ));
+    }
+    for (i, hop) in hops.iter().enumerate() {
+        let pool = &router.pools()[hop.pool_index];
+        let other_amount_threshold = if i + 1 == hops.len() {
+            opportunity
+                .route
+                .amount_in
+                .checked_add(config.min_profit)
+                .and_then(|amount| amount.checked_add(opportunity.priority_fee))
+                .ok_or_else(|| format_err!("min profit overflows"))?
+        } else {
+            hop.amount_out
+        };
+        instructions.push(swap(
+            amm_program,
+            &pool.pool_amm_keys,
+            &pool.pool_market_keys,
+            user_owner,
+            &get_associated_token_address(user_owner, &hop.input_mint),
+            &get_associated_token_address(user_owner, &hop.output_mint),
+            hop.amount_in,
+            other_amount_threshold,
+            true,
+        )?);
+    }
+    Ok(instructions)
+}
+
+// v0, a two or three pool cycle only fits with the pools' lookup tables
+pub fn build_arbitrage_transaction(
+    router: &Router,
+    amm_program: &Pubkey,
+    opportunity: &ArbOpportunity,
+    config: &ArbConfig,
+    user_owner: &Pubkey,
+    lookup_tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+) -> Result<VersionedTransaction> {
+    let instructions =
+        build_arbitrage_instructions(router, amm_program, opportunity, config, user_owner)?;
+    build_unsigned_v0_transaction(user_owner, &instructions, lookup_tables, recent_blockhash)
+}
+
+// unsigned transactions for every opportunity above the configured threshold
+pub fn find_arbitrage_transactions(
+    router: &Router,
+    amm_program: &Pubkey,
+    config: &ArbConfig,
+    user_owner: &Pubkey,
+    lookup_tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+) -> Result<Vec<(ArbOpportunity, VersionedTransaction)>> {
+    scan_arbitrage(router, config)?
+        .into_iter()
+        .filter(|opportunity| opportunity.net_profit >= config.min_profit as i64)
+        .map(|opportunity| {
+            let transaction = build_arbitrage_transaction(
+                router,
+                amm_program,
+                &opportunity,
+                config,
+                user_owner,
+                lookup_tables,
+                recent_blockhash,
+            )?;
+            Ok((opportunity, transaction))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::pool_state as pool;
+
+    fn profit(router: &Router, cycle: &[(usize, Pubkey)], amount_in: u64) -> i64 {
+        let route = simulate_cycle(router, cycle, amount_in).unwrap();
+        route.amount_out as i64 - route.amount_in as i64
+    }
+
+    #[test]
+    fn optimal_cycle_input_maximizes_profit() {
+        let (coin, pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        // coin is 20% dearer in the second pool
+        let router = Router::new(vec![
+            pool(coin, pc, 1_000_000, 1_000_000),
+            pool(coin, pc, 1_000_000, 1_200_000),
+        ]);
+        let cycle = [(0, pc), (1, coin)];
+        let amount_in = optimal_cycle_input(&router, &cycle);
+        assert!(amount_in > 0);
+        let best = profit(&router, &cycle, amount_in);
+        assert!(best > 0);
+        assert!(best >= profit(&router, &cycle, amount_in * 9 / 10));
+        assert!(best >= profit(&router, &cycle, amount_in * 11 / 10));
+    }
+
+    #[test]
+    fn optimal_cycle_input_is_zero_without_profit() {
+        let (coin, pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let router = Router::new(vec![
+            pool(coin, pc, 1_000_000, 1_000_000),
+            pool(coin, pc, 1_000_000, 1_200_000),
+        ]);
+        // buying where coin is dear and selling where it is cheap
+        assert_eq!(optimal_cycle_input(&router, &[(1, pc), (0, coin)]), 0);
+        // the same price on both sides loses the fees
+        let router = Router::new(vec![
+            pool(coin, pc, 1_000_000, 1_000_000),
+            pool(coin, pc, 2_000_000, 2_000_000),
+        ]);
+        assert_eq!(optimal_cycle_input(&router, &[(0, pc), (1, coin)]), 0);
+    }
+}
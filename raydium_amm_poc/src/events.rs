@@ -0,0 +1,415 @@
+use anyhow::{format_err, Result};
+use raydium_amm::log::{DepositLog, InitLog, LogType, SwapBaseInLog, SwapBaseOutLog, WithdrawLog};
+use solana_sdk::{instruction::CompiledInstruction, pubkey::Pubkey};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, UiInnerInstructions, UiInstruction,
+    UiLoadedAddresses,
+};
+use std::str::FromStr;
+
+use crate::raydium_amm::maths::SwapDirection;
+
+// amm instruction tags that emit a ray_log, with the index of the amm account
+const INITIALIZE2_TAG: u8 = 1;
+const DEPOSIT_TAG: u8 = 3;
+const WITHDRAW_TAG: u8 = 4;
+const SWAP_BASE_IN_TAG: u8 = 9;
+const SWAP_BASE_OUT_TAG: u8 = 11;
+const INITIALIZE2_AMM_INDEX: usize = 4;
+const AMM_INDEX: usize = 1;
+
+#[derive(Debug)]
+pub enum RayLogEvent {
+    Init(InitLog),
+    Deposit(DepositLog),
+    Withdraw(WithdrawLog),
+    SwapBaseIn(SwapBaseInLog),
+    SwapBaseOut(SwapBaseOutLog),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AmmEventKind {
+    Init,
+    Deposit,
+    Withdraw,
+    Swap,
+}
+
+impl AmmEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AmmEventKind::Init => "init",
+            AmmEventKind::Deposit => "deposit",
+            AmmEventKind::Withdraw => "withdraw",
+            AmmEventKind::Swap => "swap",
+        }
+    }
+}
+
+// one ray_log matched with the amm instruction that emitted it
+#[derive(Clone, Debug)]
+pub struct AmmEvent {
+    pub signature: String,
+    /// position among the amm invocations of the transaction
+    pub index: u32,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub pool: Pubkey,
+    pub kind: AmmEventKind,
+    /// only set for swaps
+    pub swap_direction: Option<SwapDirection>,
+    /// coin moved by the event, in or out of the pool
+    pub coin_amount: u64,
+    /// pc moved by the event, in or out of the pool
+    pub pc_amount: u64,
+    /// set for init, where the log carries the decimals
+    pub decimals: Option<(u64, u64)>,
+    /// pool open time, set for init
+    pub open_time: Option<u64>,
+}
+
+impl AmmEvent {
+    // pc per coin of the event amounts
+    pub fn price(&self, coin_decimals: u64, pc_decimals: u64) -> Option<f64> {
+        if self.coin_amount == 0 || self.pc_amount == 0 {
+            return None;
+        }
+        Some(
+            (self.pc_amount as f64) / 10_f64.powf(pc_decimals as f64) / (self.coin_amount as f64)
+                * 10_f64.powf(coin_decimals as f64),
+        )
+    }
+}
+
+pub fn decode_ray_log_event(ray_log_value: &str) -> Option<RayLogEvent> {
+    let bytes = base64::decode_config(ray_log_value.trim(), base64::STANDARD).ok()?;
+    let log_type = *bytes.first()?;
+    let event = if log_type == LogType::Init.into_u8() {
+        RayLogEvent::Init(bincode::deserialize(&bytes).ok()?)
+    } else if log_type == LogType::Deposit.into_u8() {
+        RayLogEvent::Deposit(bincode::deserialize(&bytes).ok()?)
+    } else if log_type == LogType::Withdraw.into_u8() {
+        RayLogEvent::Withdraw(bincode::deserialize(&bytes).ok()?)
+    } else if log_type == LogType::SwapBaseIn.into_u8() {
+        RayLogEvent::SwapBaseIn(bincode::deserialize(&bytes).ok()?)
+    } else if log_type == LogType::SwapBaseOut.into_u8() {
+        RayLogEvent::SwapBaseOut(bincode::deserialize(&bytes).ok()?)
+    } else {
+        return None;
+    };
+    Some(event)
+}
+
+// ray_log events in the order they were emitted
+pub fn parse_ray_logs(logs: &[String]) -> Vec<RayLogEvent> {
+    logs.iter()
+        .filter_map(|log| {
+            let start = log.find("ray_log:")?;
+            decode_ray_log_event(&log[start + "ray_log:".len()..])
+        })
+        .collect()
+}
+
+fn swap_direction_from_u64(direction: u64) -> Option<SwapDirection> {
+    match direction {
+        1 => Some(SwapDirection::PC2Coin),
+        2 => Some(SwapDirection::Coin2PC),
+        _ => None,
+    }
+}
+
+fn to_event(
+    log: &RayLogEvent,
+    pool: Pubkey,
+    signature: &str,
+    index: u32,
+    slot: u64,
+    block_time: Option<i64>,
+) -> AmmEvent {
+    let mut event = AmmEvent {
+        signature: signature.to_string(),
+        index,
+        slot,
+        block_time,
+        pool,
+        kind: AmmEventKind::Swap,
+        swap_direction: None,
+        coin_amount: 0,
+        pc_amount: 0,
+        decimals: None,
+        open_time: None,
+    };
+    match log {
+        RayLogEvent::Init(log) => {
+            event.kind = AmmEventKind::Init;
+            event.coin_amount = log.coin_amount;
+            event.pc_amount = log.pc_amount;
+            event.decimals = Some((log.coin_decimals as u64, log.pc_decimals as u64));
+            event.open_time = Some(log.time);
+        }
+        RayLogEvent::Deposit(log) => {
+            event.kind = AmmEventKind::Deposit;
+            event.coin_amount = log.deduct_coin;
+            event.pc_amount = log.deduct_pc;
+        }
+        RayLogEvent::Withdraw(log) => {
+            event.kind = AmmEventKind::Withdraw;
+            event.coin_amount = log.out_coin;
+            event.pc_amount = log.out_pc;
+        }
+        RayLogEvent::SwapBaseIn(log) => {
+            event.swap_direction = swap_direction_from_u64(log.direction);
+            let (coin, pc) = match event.swap_direction {
+                Some(SwapDirection::Coin2PC) => (log.amount_in, log.out_amount),
+                _ => (log.out_amount, log.amount_in),
+            };
+            event.coin_amount = coin;
+            event.pc_amount = pc;
+        }
+        RayLogEvent::SwapBaseOut(log) => {
+            event.swap_direction = swap_direction_from_u64(log.direction);
+            let (coin, pc) = match event.swap_direction {
+                Some(SwapDirection::Coin2PC) => (log.deduct_in, log.amount_out),
+                _ => (log.amount_out, log.deduct_in),
+            };
+            event.coin_amount = coin;
+            event.pc_amount = pc;
+        }
+    }
+    event
+}
+
+// amm pool of an instruction that emits a ray_log, None for any other instruction
+fn logged_instruction_pool(
+    account_keys: &[Pubkey],
+    accounts: &[u8],
+    data: &[u8],
+) -> Option<Pubkey> {
+    let amm_index = match *data.first()? {
+        INITIALIZE2_TAG => INITIALIZE2_AMM_INDEX,
+        DEPOSIT_TAG | WITHDRAW_TAG | SWAP_BASE_IN_TAG | SWAP_BASE_OUT_TAG => AMM_INDEX,
+        _ => return None,
+    };
+    account_keys
+        .get(*accounts.get(amm_index)? as usize)
+        .cloned()
+}
+
+fn loaded_keys(loaded_addresses: Option<UiLoadedAddresses>) -> Vec<Pubkey> {
+    match loaded_addresses {
+        Some(loaded) => loaded
+            .writable
+            .iter()
+            .chain(loaded.readonly.iter())
+            .filter_map(|key| Pubkey::from_str(key).ok())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+// pools of the logged amm invocations, in execution order
+fn logged_instruction_pools(
+    amm_program: &Pubkey,
+    account_keys: &[Pubkey],
+    instructions: &[CompiledInstruction],
+    inner_instructions: &[UiInnerInstructions],
+) -> Vec<Pubkey> {
+    let mut pools: Vec<Pubkey> = Vec::new();
+    for (i, instruction) in instructions.iter().enumerate() {
+        if account_keys.get(instruction.program_id_index as usize) == Some(amm_program) {
+            if let Some(pool) =
+                logged_instruction_pool(account_keys, &instruction.accounts, &instruction.data)
+            {
+                pools.push(pool);
+            }
+        }
+        for inner in inner_instructions
+            .iter()
+            .filter(|inner| inner.index as usize == i)
+        {
+            for inner_instruction in inner.instructions.iter() {
+                let compiled = match inner_instruction {
+                    UiInstruction::Compiled(compiled) => compiled,
+                    _ => continue,
+                };
+                if account_keys.get(compiled.program_id_index as usize) != Some(amm_program) {
+                    continue;
+                }
+                let data = match bs58::decode(&compiled.data).into_vec() {
+                    Ok(data) => data,
+                    Err(_) => continue,
+                };
+                if let Some(pool) = logged_instruction_pool(account_keys, &compiled.accounts, &data)
+                {
+                    pools.push(pool);
+                }
+            }
+        }
+    }
+    pools
+}
+
+// needs a transaction fetched with a binary encoding
+pub fn decode_amm_transaction(
+    amm_program: &Pubkey,
+    transaction: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<Vec<AmmEvent>> {
+    let meta = match &transaction.transaction.meta {
+        Some(meta) if meta.err.is_none() => meta,
+        _ => return Ok(Vec::new()),
+    };
+    let versioned = transaction
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| format_err!("transaction is not binary encoded"))?;
+    let signature = versioned
+        .signatures
+        .first()
+        .map(|signature| signature.to_string())
+        .unwrap_or_default();
+    let mut account_keys: Vec<Pubkey> = versioned.message.static_account_keys().to_vec();
+    account_keys.extend(loaded_keys(meta.loaded_addresses.clone().into()));
+    let inner_instructions: Vec<UiInnerInstructions> =
+        Option::from(meta.inner_instructions.clone()).unwrap_or_default();
+    let logs: Vec<String> = Option::from(meta.log_messages.clone()).unwrap_or_default();
+
+    let pools = logged_instruction_pools(
+        amm_program,
+        &account_keys,
+        versioned.message.instructions(),
+        &inner_instructions,
+    );
+    Ok(parse_ray_logs(&logs)
+        .iter()
+        .zip(pools.into_iter())
+        .enumerate()
+        .map(|(index, (log, pool))| {
+            to_event(
+                log,
+                pool,
+                &signature,
+                index as u32,
+                transaction.slot,
+                transaction.block_time,
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        message::Message,
+        transaction::{Transaction, VersionedTransaction},
+    };
+    use solana_transaction_status::{
+        option_serializer::OptionSerializer, EncodedTransaction, EncodedTransactionWithStatusMeta,
+        TransactionBinaryEncoding, UiTransactionStatusMeta,
+    };
+
+    // a SwapBaseInLog as the program serializes it: the log type, then
+    // amount_in, minimum_out, direction, user_source, pool_coin, pool_pc, out_amount
+    fn swap_base_in_ray_log(direction: u64, amount_in: u64, out_amount: u64) -> String {
+        let mut bytes = vec![LogType::SwapBaseIn.into_u8()];
+        for field in [amount_in, 0, direction, 0, 1_000_000, 1_000_000, out_amount].iter() {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        format!("Program log: ray_log: {}", base64::encode(bytes))
+    }
+
+    fn swap_transaction(
+        amm_program: &Pubkey,
+        pool: &Pubkey,
+        logs: Vec<String>,
+    ) -> EncodedConfirmedTransactionWithStatusMeta {
+        let instruction = Instruction::new_with_bytes(
+            *amm_program,
+            &[SWAP_BASE_IN_TAG, 0, 0, 0, 0, 0, 0, 0, 0],
+            vec![
+                AccountMeta::new_readonly(spl_token::ID, false),
+                AccountMeta::new(*pool, false),
+            ],
+        );
+        let message = Message::new(&[instruction], Some(&Pubkey::new_unique()));
+        let transaction = VersionedTransaction::from(Transaction::new_unsigned(message));
+        EncodedConfirmedTransactionWithStatusMeta {
+            slot: 42,
+            block_time: Some(1_700_000_000),
+            transaction: EncodedTransactionWithStatusMeta {
+                transaction: EncodedTransaction::Binary(
+                    base64::encode(bincode::serialize(&transaction).unwrap()),
+                    TransactionBinaryEncoding::Base64,
+                ),
+                meta: Some(UiTransactionStatusMeta {
+                    err: None,
+                    status: Ok(()),
+                    fee: 5000,
+                    pre_balances: Vec::new(),
+                    post_balances: Vec::new(),
+                    inner_instructions: OptionSerializer::None,
+                    log_messages: OptionSerializer::Some(logs),
+                    pre_token_balances: OptionSerializer::None,
+                    post_token_balances: OptionSerializer::None,
+                    rewards: OptionSerializer::None,
+                    loaded_addresses: OptionSerializer::Skip,
+                    return_data: OptionSerializer::Skip,
+                    compute_units_consumed: OptionSerializer::Skip,
+                }),
+                version: None,
+            },
+        }
+    }
+
+    #[test]
+    fn parse_ray_logs_skips_other_logs() {
+        let logs = vec![
+            "Program log: Instruction: Transfer".to_string(),
+            swap_base_in_ray_log(2, 1_000, 990),
+            "Program log: ray_log: not base64".to_string(),
+        ];
+        let events = parse_ray_logs(&logs);
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            RayLogEvent::SwapBaseIn(log) => {
+                assert_eq!(log.amount_in, 1_000);
+                assert_eq!(log.out_amount, 990);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+
+    #[test]
+    fn decode_amm_transaction_matches_logs_to_pools() {
+        let (amm_program, pool) = (Pubkey::new_unique(), Pubkey::new_unique());
+        // sell 1_000 coin for 990 pc
+        let transaction = swap_transaction(
+            &amm_program,
+            &pool,
+            vec![swap_base_in_ray_log(2, 1_000, 990)],
+        );
+        let events = decode_amm_transaction(&amm_program, &transaction).unwrap();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.pool, pool);
+        assert_eq!(event.kind, AmmEventKind::Swap);
+        assert_eq!(event.swap_direction, Some(SwapDirection::Coin2PC));
+        assert_eq!((event.coin_amount, event.pc_amount), (1_000, 990));
+        assert_eq!((event.slot, event.block_time), (42, Some(1_700_000_000)));
+        assert_eq!(event.price(0, 0), Some(0.99));
+    }
+
+    #[test]
+    fn decode_amm_transaction_ignores_other_programs() {
+        let pool = Pubkey::new_unique();
+        let transaction = swap_transaction(
+            &Pubkey::new_unique(),
+            &pool,
+            vec![swap_base_in_ray_log(2, 1_000, 990)],
+        );
+        let events = decode_amm_transaction(&Pubkey::new_unique(), &transaction).unwrap();
+        assert!(events.is_empty());
+    }
+}
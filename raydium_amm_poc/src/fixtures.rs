@@ -0,0 +1,61 @@
+// pools for unit tests, here and in the app through the test-fixtures feature
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::amm_math::{CalculateResult, PoolState};
+use crate::openbook::MarketPubkeys;
+use crate::utils::AmmKeys;
+
+// 0.25% fee and no decimals, so prices read as raw ratios
+pub fn pool(pc_amount: u64, coin_amount: u64) -> CalculateResult {
+    CalculateResult {
+        pool_pc_vault_amount: pc_amount,
+        pool_pc_decimals: 0,
+        pool_coin_vault_amount: coin_amount,
+        pool_coin_decimals: 0,
+        pool_lp_amount: 0,
+        swap_fee_numerator: 25,
+        swap_fee_denominator: 10000,
+    }
+}
+
+// pool above with the given mints and unique keys for every other account
+pub fn pool_state(
+    coin_mint: Pubkey,
+    pc_mint: Pubkey,
+    coin_amount: u64,
+    pc_amount: u64,
+) -> PoolState {
+    let key = || Box::new(Pubkey::new_unique());
+    PoolState {
+        pool: pool(pc_amount, coin_amount),
+        pool_amm_keys: AmmKeys {
+            amm_pool: Pubkey::new_unique(),
+            amm_coin_mint: coin_mint,
+            amm_pc_mint: pc_mint,
+            amm_authority: Pubkey::new_unique(),
+            amm_target: Pubkey::new_unique(),
+            amm_coin_vault: Pubkey::new_unique(),
+            amm_pc_vault: Pubkey::new_unique(),
+            amm_lp_mint: Pubkey::new_unique(),
+            amm_open_order: Pubkey::new_unique(),
+            market_program: Pubkey::new_unique(),
+            market: Pubkey::new_unique(),
+            nonce: 0,
+        },
+        pool_market_keys: MarketPubkeys {
+            market: key(),
+            req_q: key(),
+            event_q: key(),
+            bids: key(),
+            asks: key(),
+            coin_vault: key(),
+            pc_vault: key(),
+            vault_signer_key: key(),
+            coin_mint: Box::new(coin_mint),
+            pc_mint: Box::new(pc_mint),
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+        },
+    }
+}
@@ -0,0 +1,319 @@
+use anyhow::{format_err, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_sdk::{
+    hash::Hash,
+    message::{v0, VersionedMessage},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::VersionedTransaction,
+};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::transaction::{serialize_transaction, UnsignedSwapTx};
+
+// a bundle holds at most this many transactions, tip included
+pub const MAX_BUNDLE_TRANSACTIONS: usize = 5;
+
+#[derive(Clone, Debug)]
+pub struct JitoConfig {
+    /// one of the tip accounts published by the block engine
+    pub tip_account: Pubkey,
+    pub tip_lamports: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BundleStatus {
+    pub bundle_id: String,
+    pub transactions: Vec<String>,
+    pub slot: u64,
+    /// processed, confirmed or finalized
+    pub confirmation_status: String,
+    pub err: Value,
+}
+
+pub struct JitoClient {
+    http: reqwest::blocking::Client,
+    url: String,
+}
+
+impl JitoClient {
+    // block engine json rpc endpoint, e.g. https://mainnet.block-engine.jito.wtf/api/v1/bundles
+    pub fn new(block_engine_url: &str) -> Self {
+        JitoClient {
+            http: reqwest::blocking::Client::new(),
+            url: block_engine_url.to_string(),
+        }
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let response: Value = self
+            .http
+            .post(&self.url)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": method,
+                "params": params,
+            }))
+            .send()?
+            .error_for_status()?
+            .json()?;
+        if let Some(error) = response.get("error") {
+            return Err(format_err!("{} failed: {}", method, error));
+        }
+        response
+            .get("result")
+            .cloned()
+            .ok_or_else(|| format_err!("{} returned no result", method))
+    }
+
+    // returns the bundle id
+    pub fn send_bundle(&self, transactions: &[VersionedTransaction]) -> Result<String> {
+        if transactions.is_empty() || transactions.len() > MAX_BUNDLE_TRANSACTIONS {
+            return Err(format_err!(
+                "bundle must hold 1 to {} transactions, got {}",
+                MAX_BUNDLE_TRANSACTIONS,
+                transactions.len()
+            ));
+        }
+        let encoded = transactions
+            .iter()
+            .map(serialize_transaction)
+            .collect::<Result<Vec<String>>>()?;
+        let result = self.call("sendBundle", json!([encoded, { "encoding": "base64" }]))?;
+        result
+            .as_str()
+            .map(|bundle_id| bundle_id.to_string())
+            .ok_or_else(|| format_err!("sendBundle returned {}", result))
+    }
+
+    // None for bundles the block engine has not landed yet
+    pub fn get_bundle_status(&self, bundle_id: &str) -> Result<Option<BundleStatus>> {
+        let result = self.call("getBundleStatuses", json!([[bundle_id]]))?;
+        match result.get("value").and_then(|value| value.get(0)) {
+            Some(status) if !status.is_null() => Ok(Some(serde_json::from_value(status.clone())?)),
+            _ => Ok(None),
+        }
+    }
+
+    // polls until the bundle is confirmed, fails on a bundle error or timeout
+    pub fn wait_for_bundle(
+        &self,
+        bundle_id: &str,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<BundleStatus> {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.get_bundle_status(bundle_id)? {
+                if !status.err.is_null() && status.err.get("Ok").is_none() {
+                    return Err(format_err!("bundle {} failed: {}", bundle_id, status.err));
+                }
+                if status.confirmation_status == "confirmed"
+                    || status.confirmation_status == "finalized"
+                {
+                    return Ok(status);
+                }
+            }
+            if started.elapsed() >= timeout {
+                return Err(format_err!("bundle {} not confirmed in time", bundle_id));
+            }
+            thread::sleep(poll_interval);
+        }
+    }
+}
+
+pub fn build_tip_transaction(
+    payer: &Keypair,
+    config: &JitoConfig,
+    recent_blockhash: Hash,
+) -> Result<VersionedTransaction> {
+    let instruction =
+        system_instruction::transfer(&payer.pubkey(), &config.tip_account, config.tip_lamports);
+    let message = v0::Message::try_compile(&payer.pubkey(), &[instruction], &[], recent_blockhash)?;
+    Ok(VersionedTransaction::try_new(
+        VersionedMessage::V0(message),
+        &[payer],
+    )?)
+}
+
+// signs swap transactions from the swap builder, as they are, with the user key
+pub fn sign_swap_transaction(
+    swap_tx: &UnsignedSwapTx,
+    signer: &Keypair,
+) -> Result<VersionedTransaction> {
+    Ok(VersionedTransaction::try_new(
+        swap_tx.transaction.message.clone(),
+        &[signer],
+    )?)
+}
+
+// the swaps in order, then the tip, so the tip only lands if every swap does
+pub fn send_swap_bundle(
+    client: &JitoClient,
+    config: &JitoConfig,
+    payer: &Keypair,
+    swap_txs: &[UnsignedSwapTx],
+) -> Result<String> {
+    let recent_blockhash = match swap_txs.first() {
+        Some(swap_tx) => *swap_tx.transaction.message.recent_blockhash(),
+        None => return Err(format_err!("bundle has no swaps")),
+    };
+    let mut transactions = swap_txs
+        .iter()
+        .map(|swap_tx| sign_swap_transaction(swap_tx, payer))
+        .collect::<Result<Vec<VersionedTransaction>>>()?;
+    transactions.push(build_tip_transaction(payer, config, recent_blockhash)?);
+    client.send_bundle(&transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread::JoinHandle,
+    };
+
+    // answers one json rpc request per response, in order, and hands back the
+    // request bodies
+    fn stub_block_engine(responses: Vec<Value>) -> (String, JoinHandle<Vec<Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v1/bundles", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    let lower = line.to_ascii_lowercase();
+                    if let Some(value) = lower.strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                requests.push(serde_json::from_slice(&body).unwrap());
+                let body = response.to_string();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn tip_transaction() -> VersionedTransaction {
+        let config = JitoConfig {
+            tip_account: Pubkey::new_unique(),
+            tip_lamports: 1_000,
+        };
+        build_tip_transaction(&Keypair::new(), &config, Hash::default()).unwrap()
+    }
+
+    fn bundle_status(confirmation_status: &str, err: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {
+                "context": { "slot": 100 },
+                "value": [{
+                    "bundle_id": "bundle",
+                    "transactions": ["signature"],
+                    "slot": 99,
+                    "confirmation_status": confirmation_status,
+                    "err": err,
+                }],
+            },
+        })
+    }
+
+    #[test]
+    fn send_bundle_posts_base64_transactions() {
+        let (url, server) = stub_block_engine(vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "result": "bundle" }),
+        ]);
+        let transaction = tip_transaction();
+        let bundle_id = JitoClient::new(&url)
+            .send_bundle(&[transaction.clone()])
+            .unwrap();
+        assert_eq!(bundle_id, "bundle");
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0]["method"], "sendBundle");
+        assert_eq!(requests[0]["params"][1]["encoding"], "base64");
+        assert_eq!(
+            requests[0]["params"][0],
+            json!([serialize_transaction(&transaction).unwrap()])
+        );
+    }
+
+    #[test]
+    fn send_bundle_checks_the_size_before_sending() {
+        let client = JitoClient::new("http://127.0.0.1:1");
+        assert!(client.send_bundle(&[]).is_err());
+        let transactions = vec![tip_transaction(); MAX_BUNDLE_TRANSACTIONS + 1];
+        assert!(client.send_bundle(&transactions).is_err());
+    }
+
+    #[test]
+    fn get_bundle_status_reads_landed_and_unknown_bundles() {
+        let (url, server) = stub_block_engine(vec![
+            bundle_status("confirmed", json!({ "Ok": null })),
+            json!({ "jsonrpc": "2.0", "id": 1, "result": { "context": { "slot": 100 }, "value": [null] } }),
+        ]);
+        let client = JitoClient::new(&url);
+        let status = client.get_bundle_status("bundle").unwrap().unwrap();
+        assert_eq!(status.slot, 99);
+        assert_eq!(status.confirmation_status, "confirmed");
+        assert!(client.get_bundle_status("bundle").unwrap().is_none());
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0]["method"], "getBundleStatuses");
+        assert_eq!(requests[0]["params"], json!([["bundle"]]));
+    }
+
+    #[test]
+    fn wait_for_bundle_polls_until_confirmed() {
+        let (url, server) = stub_block_engine(vec![
+            bundle_status("processed", json!({ "Ok": null })),
+            bundle_status("finalized", json!({ "Ok": null })),
+        ]);
+        let status = JitoClient::new(&url)
+            .wait_for_bundle("bundle", Duration::from_secs(5), Duration::from_millis(1))
+            .unwrap();
+        assert_eq!(status.confirmation_status, "finalized");
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn block_engine_errors_are_returned() {
+        let (url, server) = stub_block_engine(vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": -32602, "message": "bad" } }),
+            bundle_status("confirmed", json!({ "Err": "BundleFailed" })),
+        ]);
+        let client = JitoClient::new(&url);
+        assert!(client.send_bundle(&[tip_transaction()]).is_err());
+        assert!(client
+            .wait_for_bundle("bundle", Duration::from_secs(5), Duration::from_millis(1))
+            .is_err());
+        server.join().unwrap();
+    }
+}
@@ -5,4 +5,20 @@ pub mod utils;
 pub mod amm_math;
 pub mod rpc;
 pub mod openbook;
-pub mod instruction;
\ No newline at end of file
+pub mod instruction;
+pub mod nonblocking;
+pub mod router;
+pub mod split;
+pub mod arbitrage;
+pub mod mev;
+pub mod events;
+pub mod transaction;
+pub mod lookup_table;
+pub mod priority_fee;
+#[cfg(feature = "jito")]
+pub mod jito;
+pub mod risk;
+pub mod token;
+pub mod pool_discovery;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixtures;
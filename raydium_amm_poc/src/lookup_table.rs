@@ -0,0 +1,202 @@
+use anyhow::{format_err, Result};
+use solana_address_lookup_table_program::{
+    instruction::{create_lookup_table, extend_lookup_table},
+    state::AddressLookupTable,
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    address_lookup_table_account::AddressLookupTableAccount,
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use spl_token::ID;
+use std::{thread, time::Duration};
+
+use crate::amm_math::PoolState;
+
+// addresses per extend instruction, keeps each transaction below the size limit
+const EXTEND_CHUNK_SIZE: usize = 20;
+// about a slot, and how many to wait for the table to become usable
+const SLOT_POLL_INTERVAL: Duration = Duration::from_millis(400);
+const SLOT_POLL_ATTEMPTS: usize = 30;
+
+// accounts every swap on the pool reads, the user accounts are left out
+pub fn pool_lookup_table_addresses(amm_program: &Pubkey, pool: &PoolState) -> Vec<Pubkey> {
+    let amm_keys = &pool.pool_amm_keys;
+    let market_keys = &pool.pool_market_keys;
+    let mut addresses: Vec<Pubkey> = vec![
+        *amm_program,
+        ID,
+        amm_keys.amm_pool,
+        amm_keys.amm_authority,
+        amm_keys.amm_open_order,
+        amm_keys.amm_target,
+        amm_keys.amm_coin_vault,
+        amm_keys.amm_pc_vault,
+        amm_keys.amm_coin_mint,
+        amm_keys.amm_pc_mint,
+        amm_keys.market_program,
+        amm_keys.market,
+        *market_keys.bids,
+        *market_keys.asks,
+        *market_keys.event_q,
+        *market_keys.coin_vault,
+        *market_keys.pc_vault,
+        *market_keys.vault_signer_key,
+    ];
+    let mut seen: Vec<Pubkey> = Vec::new();
+    addresses.retain(|address| {
+        if seen.contains(address) {
+            false
+        } else {
+            seen.push(*address);
+            true
+        }
+    });
+    addresses
+}
+
+// create instruction first, then the extends. recent_slot must be a recent
+// finalized slot, it seeds the table address
+pub fn create_lookup_table_instructions(
+    authority: &Pubkey,
+    payer: &Pubkey,
+    recent_slot: u64,
+    addresses: &[Pubkey],
+) -> (Pubkey, Vec<Instruction>) {
+    let (create_instruction, lookup_table) = create_lookup_table(*authority, *payer, recent_slot);
+    let mut instructions: Vec<Instruction> = vec![create_instruction];
+    instructions.extend(extend_lookup_table_instructions(
+        &lookup_table,
+        authority,
+        payer,
+        addresses,
+    ));
+    (lookup_table, instructions)
+}
+
+pub fn extend_lookup_table_instructions(
+    lookup_table: &Pubkey,
+    authority: &Pubkey,
+    payer: &Pubkey,
+    addresses: &[Pubkey],
+) -> Vec<Instruction> {
+    addresses
+        .chunks(EXTEND_CHUNK_SIZE)
+        .map(|chunk| extend_lookup_table(*lookup_table, *authority, Some(*payer), chunk.to_vec()))
+        .collect()
+}
+
+// one transaction per instruction. addresses are only usable from the slot
+// after the last extend, so it returns once that slot was reached
+pub fn create_pool_lookup_table(
+    client: &RpcClient,
+    amm_program: &Pubkey,
+    pool: &PoolState,
+    payer: &Keypair,
+) -> Result<Pubkey> {
+    let recent_slot = client.get_slot_with_commitment(CommitmentConfig::finalized())?;
+    let (lookup_table, instructions) = create_lookup_table_instructions(
+        &payer.pubkey(),
+        &payer.pubkey(),
+        recent_slot,
+        &pool_lookup_table_addresses(amm_program, pool),
+    );
+    for instruction in instructions {
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&payer.pubkey()),
+            &[payer],
+            client.get_latest_blockhash()?,
+        );
+        client.send_and_confirm_transaction(&transaction)?;
+    }
+    wait_until_usable(client, &lookup_table)?;
+    Ok(lookup_table)
+}
+
+fn wait_until_usable(client: &RpcClient, lookup_table: &Pubkey) -> Result<()> {
+    let account = client.get_account(lookup_table)?;
+    let last_extended_slot = AddressLookupTable::deserialize(&account.data)
+        .map_err(|e| format_err!("lookup table {}: {}", lookup_table, e))?
+        .meta
+        .last_extended_slot;
+    for _ in 0..SLOT_POLL_ATTEMPTS {
+        if client.get_slot_with_commitment(CommitmentConfig::confirmed())? > last_extended_slot {
+            return Ok(());
+        }
+        thread::sleep(SLOT_POLL_INTERVAL);
+    }
+    Err(format_err!(
+        "lookup table {} extended at slot {} and no later slot was reached",
+        lookup_table,
+        last_extended_slot
+    ))
+}
+
+pub fn fetch_lookup_table(
+    client: &RpcClient,
+    lookup_table: &Pubkey,
+) -> Result<AddressLookupTableAccount> {
+    let account = client.get_account(lookup_table)?;
+    let table = AddressLookupTable::deserialize(&account.data)
+        .map_err(|e| format_err!("lookup table {}: {}", lookup_table, e))?;
+    Ok(AddressLookupTableAccount {
+        key: *lookup_table,
+        addresses: table.addresses.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::pool_state;
+    use solana_address_lookup_table_program::instruction::derive_lookup_table_address;
+
+    #[test]
+    fn pool_addresses_are_listed_once() {
+        let mut pool = pool_state(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 2_000);
+        // a pool whose target orders share the open orders account
+        pool.pool_amm_keys.amm_target = pool.pool_amm_keys.amm_open_order;
+        let amm_program = Pubkey::new_unique();
+        let addresses = pool_lookup_table_addresses(&amm_program, &pool);
+        assert_eq!(addresses.len(), 17);
+        assert_eq!(addresses[0], amm_program);
+        assert_eq!(addresses[1], ID);
+        for (i, address) in addresses.iter().enumerate() {
+            assert!(!addresses[i + 1..].contains(address));
+        }
+        assert!(addresses.contains(&pool.pool_amm_keys.amm_pool));
+        assert!(addresses.contains(&*pool.pool_market_keys.vault_signer_key));
+    }
+
+    #[test]
+    fn create_comes_first_then_extends_in_chunks() {
+        let (authority, payer) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let addresses: Vec<Pubkey> = (0..45).map(|_| Pubkey::new_unique()).collect();
+        let (lookup_table, instructions) =
+            create_lookup_table_instructions(&authority, &payer, 77, &addresses);
+        assert_eq!(lookup_table, derive_lookup_table_address(&authority, 77).0);
+        assert_eq!(instructions.len(), 4);
+        assert_eq!(instructions[0], create_lookup_table(authority, payer, 77).0);
+        for (instruction, chunk) in instructions[1..]
+            .iter()
+            .zip(addresses.chunks(EXTEND_CHUNK_SIZE))
+        {
+            assert_eq!(
+                *instruction,
+                extend_lookup_table(lookup_table, authority, Some(payer), chunk.to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn no_addresses_need_no_extend() {
+        let (_, instructions) =
+            create_lookup_table_instructions(&Pubkey::new_unique(), &Pubkey::new_unique(), 1, &[]);
+        assert_eq!(instructions.len(), 1);
+    }
+}
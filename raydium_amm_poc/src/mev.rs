@@ -0,0 +1,269 @@
+use anyhow::{format_err, Result};
+use raydium_amm::math::{CheckedCeilDiv, U128};
+
+use crate::amm_math::{min_amount_with_slippage, CalculateResult, TEN_THOUSAND};
+use crate::raydium_amm::maths::{Calculator, SwapDirection};
+
+#[derive(Clone, Debug)]
+pub struct SandwichExposure {
+    /// attacker buy placed right before our swap, in our input token
+    pub front_run_amount: u64,
+    /// attacker profit in our input token, pool fees paid, never negative
+    pub attacker_profit: u64,
+    /// our output with nobody in front of us
+    pub expected_amount_out: u64,
+    /// our output after the most profitable front run
+    pub worst_amount_out: u64,
+    /// our min out for the requested slippage
+    pub min_amount_out: u64,
+    /// widest slippage at which no sandwich makes a profit
+    pub max_safe_slippage_bps: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Reserves {
+    reserve_in: u64,
+    reserve_out: u64,
+}
+
+struct Sandwich {
+    victim_amount_out: u64,
+    attacker_profit: i128,
+}
+
+// swap base in on a pool seen from the input side
+fn swap_base_in(state: &CalculateResult, reserves: Reserves, amount_in: u64) -> u64 {
+    if amount_in == 0 {
+        return 0;
+    }
+    let swap_fee = U128::from(amount_in)
+        .checked_mul(state.swap_fee_numerator.into())
+        .unwrap()
+        .checked_ceil_div(state.swap_fee_denominator.into())
+        .unwrap()
+        .0;
+    let swap_in_after_deduct_fee = U128::from(amount_in).checked_sub(swap_fee).unwrap();
+    // Coin2PC reads pc as the output reserve and coin as the input reserve
+    Calculator::swap_token_amount_base_in(
+        swap_in_after_deduct_fee,
+        reserves.reserve_out.into(),
+        reserves.reserve_in.into(),
+        SwapDirection::Coin2PC,
+    )
+    .as_u64()
+}
+
+// front run, victim, back run, all against the same pool
+fn sandwich(
+    state: &CalculateResult,
+    reserves: Reserves,
+    victim_amount_in: u64,
+    front_run_amount: u64,
+) -> Option<Sandwich> {
+    let front_run_out = swap_base_in(state, reserves, front_run_amount);
+    let after_front_run = Reserves {
+        reserve_in: reserves.reserve_in.checked_add(front_run_amount)?,
+        reserve_out: reserves.reserve_out.checked_sub(front_run_out)?,
+    };
+    let victim_amount_out = swap_base_in(state, after_front_run, victim_amount_in);
+    let after_victim = Reserves {
+        reserve_in: after_front_run.reserve_in.checked_add(victim_amount_in)?,
+        reserve_out: after_front_run.reserve_out.checked_sub(victim_amount_out)?,
+    };
+    // the back run sells front_run_out the other way
+    let back_run_out = swap_base_in(
+        state,
+        Reserves {
+            reserve_in: after_victim.reserve_out,
+            reserve_out: after_victim.reserve_in,
+        },
+        front_run_out,
+    );
+    Some(Sandwich {
+        victim_amount_out,
+        attacker_profit: back_run_out as i128 - front_run_amount as i128,
+    })
+}
+
+// largest front run that still leaves the victim at least min_amount_out
+fn max_front_run(
+    state: &CalculateResult,
+    reserves: Reserves,
+    victim_amount_in: u64,
+    min_amount_out: u64,
+) -> u64 {
+    let fits =
+        |front_run_amount: u64| match sandwich(state, reserves, victim_amount_in, front_run_amount)
+        {
+            Some(result) => result.victim_amount_out >= min_amount_out,
+            None => false,
+        };
+    let (mut low, mut high) = (0u64, 1u64);
+    while fits(high) {
+        low = high;
+        if high == u64::MAX {
+            return high;
+        }
+        high = high.saturating_mul(2);
+    }
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if fits(mid) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+// attacker profit is unimodal in the front run size, so a ternary search over
+// the sizes the victim tolerates finds the best one
+fn best_sandwich(
+    state: &CalculateResult,
+    reserves: Reserves,
+    victim_amount_in: u64,
+    min_amount_out: u64,
+) -> (u64, Sandwich) {
+    let profit = |front_run_amount: u64| {
+        sandwich(state, reserves, victim_amount_in, front_run_amount)
+            .map(|result| result.attacker_profit)
+            .unwrap_or(i128::MIN)
+    };
+    let (mut low, mut high) = (
+        0u64,
+        max_front_run(state, reserves, victim_amount_in, min_amount_out),
+    );
+    while high - low > 2 {
+        let third = (high - low) / 3;
+        let (m1, m2) = (low + third, high - third);
+        if profit(m1) < profit(m2) {
+            low = m1 + 1;
+        } else {
+            high = m2;
+        }
+    }
+    let best = (low..=high).max_by_key(|amount| profit(*amount)).unwrap();
+    (
+        best,
+        sandwich(state, reserves, victim_amount_in, best).unwrap(),
+    )
+}
+
+pub fn estimate_sandwich_exposure(
+    state: &CalculateResult,
+    swap_direction: SwapDirection,
+    amount_in: u64,
+    slippage_bps: u64,
+) -> Result<SandwichExposure> {
+    if slippage_bps > TEN_THOUSAND {
+        return Err(format_err!("slippage must not exceed 100%"));
+    }
+    let reserves = match swap_direction {
+        SwapDirection::Coin2PC => Reserves {
+            reserve_in: state.pool_coin_vault_amount,
+            reserve_out: state.pool_pc_vault_amount,
+        },
+        SwapDirection::PC2Coin => Reserves {
+            reserve_in: state.pool_pc_vault_amount,
+            reserve_out: state.pool_coin_vault_amount,
+        },
+    };
+    if reserves.reserve_in == 0 || reserves.reserve_out == 0 {
+        return Err(format_err!("pool has no liquidity"));
+    }
+    let expected_amount_out = swap_base_in(state, reserves, amount_in);
+    let min_amount_out = min_amount_with_slippage(expected_amount_out, slippage_bps);
+    let (front_run_amount, worst) = best_sandwich(state, reserves, amount_in, min_amount_out);
+
+    // profit only grows as min out loosens, so search the widest safe slippage
+    let unprofitable = |slippage_bps: u64| {
+        let min_amount_out = min_amount_with_slippage(expected_amount_out, slippage_bps);
+        best_sandwich(state, reserves, amount_in, min_amount_out)
+            .1
+            .attacker_profit
+            <= 0
+    };
+    let (mut low, mut high) = (0u64, TEN_THOUSAND);
+    if unprofitable(high) {
+        low = high;
+    }
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if unprofitable(mid) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    Ok(SandwichExposure {
+        front_run_amount: if worst.attacker_profit > 0 {
+            front_run_amount
+        } else {
+            0
+        },
+        attacker_profit: worst.attacker_profit.max(0) as u64,
+        expected_amount_out,
+        worst_amount_out: if worst.attacker_profit > 0 {
+            worst.victim_amount_out
+        } else {
+            expected_amount_out
+        },
+        min_amount_out,
+        max_safe_slippage_bps: low,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn pool() -> CalculateResult {
+        fixtures::pool(1_000_000, 1_000_000)
+    }
+
+    #[test]
+    fn estimate_sandwich_exposure_without_slippage_is_safe() {
+        let exposure =
+            estimate_sandwich_exposure(&pool(), SwapDirection::PC2Coin, 100_000, 0).unwrap();
+        assert_eq!(exposure.front_run_amount, 0);
+        assert_eq!(exposure.attacker_profit, 0);
+        assert_eq!(exposure.worst_amount_out, exposure.expected_amount_out);
+        assert_eq!(exposure.min_amount_out, exposure.expected_amount_out);
+    }
+
+    #[test]
+    fn estimate_sandwich_exposure_keeps_the_victim_above_min_out() {
+        let exposure =
+            estimate_sandwich_exposure(&pool(), SwapDirection::Coin2PC, 100_000, 500).unwrap();
+        assert!(exposure.front_run_amount > 0);
+        assert!(exposure.attacker_profit > 0);
+        assert!(exposure.worst_amount_out < exposure.expected_amount_out);
+        assert!(exposure.worst_amount_out >= exposure.min_amount_out);
+        assert!(exposure.max_safe_slippage_bps < 500);
+    }
+
+    #[test]
+    fn estimate_sandwich_exposure_max_safe_slippage_is_the_boundary() {
+        let state = pool();
+        let safe_bps = estimate_sandwich_exposure(&state, SwapDirection::Coin2PC, 100_000, 500)
+            .unwrap()
+            .max_safe_slippage_bps;
+        let profit = |slippage_bps: u64| {
+            estimate_sandwich_exposure(&state, SwapDirection::Coin2PC, 100_000, slippage_bps)
+                .unwrap()
+                .attacker_profit
+        };
+        assert_eq!(profit(safe_bps), 0);
+        assert!(profit(safe_bps + 1) > 0);
+    }
+
+    #[test]
+    fn estimate_sandwich_exposure_rejects_slippage_above_100_percent() {
+        assert!(
+            estimate_sandwich_exposure(&pool(), SwapDirection::Coin2PC, 100_000, 10_001).is_err()
+        );
+    }
+}
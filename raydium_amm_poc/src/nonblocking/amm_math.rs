@@ -0,0 +1,78 @@
+use anyhow::{format_err, Result};
+use futures::future::try_join_all;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+
+use crate::amm_math::{
+    calc_coin_supply_from_mint_data, calc_pool_valut_amounts_from_accounts, decode_amm_info,
+    pool_valut_amounts_keys, CalculateResult, PoolState,
+};
+use crate::nonblocking::{openbook::get_keys_for_market, rpc::get_multiple_accounts};
+use crate::openbook::MarketPubkeys;
+use crate::raydium_amm::state::AmmInfo;
+use crate::utils::{load_amm_keys, AmmKeys};
+
+pub async fn calc_pool_valut_amounts(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    amm_pool_key: &Pubkey,
+    amm_keys: &AmmKeys,
+    market_keys: &MarketPubkeys,
+    amm: &AmmInfo,
+) -> Result<CalculateResult> {
+    let load_pubkeys: Vec<Pubkey> = pool_valut_amounts_keys(amm_pool_key, amm_keys, market_keys);
+    let rsps = get_multiple_accounts(client, &load_pubkeys).await?;
+    calc_pool_valut_amounts_from_accounts(amm_program_key, market_keys, amm, &rsps)
+}
+
+pub async fn load_state(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    amm_pool_key: &Pubkey,
+) -> Result<PoolState> {
+    let account = client
+        .get_account_with_commitment(amm_pool_key, CommitmentConfig::processed())
+        .await?
+        .value
+        .ok_or_else(|| format_err!("amm pool {} not found", amm_pool_key))?;
+    let amm_info: AmmInfo = decode_amm_info(amm_program_key, amm_pool_key, &account)?;
+    let amm_keys: AmmKeys = load_amm_keys(&amm_program_key, &amm_pool_key, &amm_info)?;
+    let market_keys: MarketPubkeys =
+        get_keys_for_market(&client, &amm_keys.market_program, &amm_keys.market).await?;
+    let calculate_result: CalculateResult = calc_pool_valut_amounts(
+        &client,
+        &amm_program_key,
+        &amm_pool_key,
+        &amm_keys,
+        &market_keys,
+        &amm_info,
+    )
+    .await?;
+
+    Ok(PoolState {
+        pool: calculate_result,
+        pool_amm_keys: amm_keys,
+        pool_market_keys: market_keys,
+    })
+}
+
+// loads every pool concurrently, results are in the same order as amm_pool_keys
+pub async fn load_states(
+    client: &RpcClient,
+    amm_program_key: &Pubkey,
+    amm_pool_keys: &[Pubkey],
+) -> Result<Vec<PoolState>> {
+    try_join_all(
+        amm_pool_keys
+            .iter()
+            .map(|amm_pool_key| load_state(client, amm_program_key, amm_pool_key)),
+    )
+    .await
+}
+
+pub async fn calc_coin_market_cap(pool_state: &PoolState, client: &RpcClient) -> Result<f64> {
+    let account_data = client
+        .get_account_data(&pool_state.pool_amm_keys.amm_coin_mint)
+        .await?;
+    calc_coin_supply_from_mint_data(&account_data)
+}
@@ -0,0 +1,4 @@
+pub mod amm_math;
+pub mod openbook;
+pub mod priority_fee;
+pub mod rpc;
@@ -0,0 +1,14 @@
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::openbook::{parse_keys_for_market, MarketPubkeys};
+
+pub async fn get_keys_for_market<'a>(
+    client: &'a RpcClient,
+    market_key: &'a Pubkey,
+    market: &'a Pubkey,
+) -> Result<MarketPubkeys> {
+    let account_data: Vec<u8> = client.get_account_data(&market).await?;
+    parse_keys_for_market(market_key, market, &account_data)
+}
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    address_lookup_table_account::AddressLookupTableAccount, instruction::Instruction,
+    pubkey::Pubkey,
+};
+
+use crate::amm_math::PoolState;
+use crate::priority_fee::{
+    percentile_fee, priced_swap_tx_config, quoted_min_amount_out, simulated_units,
+    simulation_config, simulation_transaction, swap_simulation_instructions,
+    swap_writable_accounts, PriorityFeeConfig,
+};
+use crate::transaction::SwapTxConfig;
+
+pub async fn simulate_compute_units(
+    client: &RpcClient,
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<u64> {
+    let transaction = simulation_transaction(payer, instructions, lookup_tables)?;
+    simulated_units(
+        client
+            .simulate_transaction_with_config(&transaction, simulation_config())
+            .await?
+            .value,
+    )
+}
+
+pub async fn recent_priority_fee(
+    client: &RpcClient,
+    writable_accounts: &[Pubkey],
+    percentile: u8,
+) -> Result<u64> {
+    percentile_fee(
+        client
+            .get_recent_prioritization_fees(writable_accounts)
+            .await?
+            .iter()
+            .map(|fee| fee.prioritization_fee)
+            .collect(),
+        percentile,
+    )
+}
+
+pub async fn estimate_swap_tx_config(
+    client: &RpcClient,
+    amm_program: &Pubkey,
+    pool: &PoolState,
+    user_owner: &Pubkey,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    slippage_bps: u64,
+    config: &SwapTxConfig,
+    fee_config: &PriorityFeeConfig,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<SwapTxConfig> {
+    let instructions = swap_simulation_instructions(
+        amm_program,
+        pool,
+        user_owner,
+        input_mint,
+        amount_in,
+        quoted_min_amount_out(pool, input_mint, amount_in, slippage_bps)?,
+        config,
+    )?;
+    let units_consumed =
+        simulate_compute_units(client, user_owner, &instructions, lookup_tables).await?;
+    let compute_unit_price =
+        recent_priority_fee(client, &swap_writable_accounts(pool), fee_config.percentile).await?;
+    Ok(priced_swap_tx_config(
+        config,
+        fee_config,
+        units_consumed,
+        compute_unit_price,
+    ))
+}
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_request::RpcRequest,
+    rpc_response::{RpcResult, RpcSimulateTransactionResult},
+};
+use solana_sdk::{
+    account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey, transaction::Transaction,
+};
+
+use crate::rpc::{deserialize_account, simulate_transaction_params, MULTIPLE_ACCOUNTS_LIMIT};
+
+pub async fn get_account<T>(client: &RpcClient, amm_pool_key: &Pubkey) -> Result<Option<T>>
+where
+    T: Copy,
+{
+    if let Some(account) = client
+        .get_account_with_commitment(amm_pool_key, CommitmentConfig::processed())
+        .await?
+        .value
+    {
+        Ok(Some(deserialize_account::<T>(account.data.as_slice())?))
+    } else {
+        Ok(None)
+    }
+}
+
+// any number of keys, fetched in chunks the rpc accepts
+pub async fn get_multiple_accounts(
+    client: &RpcClient,
+    pubkeys: &[Pubkey],
+) -> Result<Vec<Option<Account>>> {
+    let mut accounts: Vec<Option<Account>> = Vec::with_capacity(pubkeys.len());
+    for chunk in pubkeys.chunks(MULTIPLE_ACCOUNTS_LIMIT) {
+        accounts.extend(client.get_multiple_accounts(chunk).await?);
+    }
+    Ok(accounts)
+}
+
+pub async fn simulate_transaction(
+    client: &RpcClient,
+    transaction: &Transaction,
+    sig_verify: bool,
+    cfg: CommitmentConfig,
+) -> RpcResult<RpcSimulateTransactionResult> {
+    client
+        .send(
+            RpcRequest::SimulateTransaction,
+            simulate_transaction_params(transaction, sig_verify, cfg),
+        )
+        .await
+}
@@ -63,9 +63,17 @@ pub fn get_keys_for_market<'a>(
     market: &'a Pubkey,
 ) -> Result<MarketPubkeys> {
     let account_data: Vec<u8> = client.get_account_data(&market)?;
-    let words: Cow<[u64]> = remove_dex_account_padding(&account_data)?;
+    parse_keys_for_market(market_key, market, &account_data)
+}
+
+pub fn parse_keys_for_market(
+    market_key: &Pubkey,
+    market: &Pubkey,
+    account_data: &[u8],
+) -> Result<MarketPubkeys> {
+    let words: Cow<[u64]> = remove_dex_account_padding(account_data)?;
     let market_state: MarketState = {
-        let account_flags = Market::account_flags(&account_data)?;
+        let account_flags = Market::account_flags(account_data)?;
         if account_flags.intersects(AccountFlag::Permissioned) {
             println!("MarketStateV2");
             let state = transmute_one_pedantic::<MarketStateV2>(transmute_to_bytes(&words))
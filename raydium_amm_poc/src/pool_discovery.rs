@@ -0,0 +1,182 @@
+use anyhow::Result;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_program::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use spl_token::state::Account;
+use std::mem::size_of;
+
+use crate::raydium_amm::state::AmmInfo;
+use crate::rpc::{deserialize_account, get_multiple_accounts};
+
+#[derive(Clone, Copy, Debug)]
+pub enum AmmMintField {
+    Coin,
+    Pc,
+    Lp,
+}
+
+impl AmmMintField {
+    // byte offset in the amm account data
+    pub fn offset(&self) -> usize {
+        let amm = AmmInfo::default();
+        let field = match self {
+            AmmMintField::Coin => &amm.coin_vault_mint,
+            AmmMintField::Pc => &amm.pc_vault_mint,
+            AmmMintField::Lp => &amm.lp_mint,
+        };
+        field as *const Pubkey as usize - &amm as *const AmmInfo as usize
+    }
+}
+
+// initialized amm pools whose mints match every (field, mint) pair, unsorted
+pub fn find_pools(
+    client: &RpcClient,
+    amm_program: &Pubkey,
+    fields: &[(AmmMintField, &Pubkey)],
+) -> Result<Vec<(Pubkey, AmmInfo)>> {
+    let mut filters = vec![RpcFilterType::DataSize(size_of::<AmmInfo>() as u64)];
+    for (field, mint) in fields.iter() {
+        filters.push(RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            field.offset(),
+            mint.as_ref(),
+        )));
+    }
+    let config = RpcProgramAccountsConfig {
+        filters: Some(filters),
+        // amm accounts are too large for the default base58
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let mut pools: Vec<(Pubkey, AmmInfo)> = Vec::new();
+    for (pool, account) in client
+        .get_program_accounts_with_config(amm_program, config)?
+        .iter()
+    {
+        let amm_info = deserialize_account::<AmmInfo>(&account.data)?;
+        if amm_info.status != 0 {
+            pools.push((*pool, amm_info));
+        }
+    }
+    Ok(pools)
+}
+
+// deepest first, by the vault balance of mint alone. the other vaults hold
+// different mints whose amounts are not comparable without prices, while the
+// mint's own reserve is, and within one pair it orders pools like both would
+pub fn sort_by_liquidity(
+    client: &RpcClient,
+    pools: Vec<(Pubkey, AmmInfo)>,
+    mint: &Pubkey,
+) -> Result<Vec<(Pubkey, AmmInfo)>> {
+    let vaults: Vec<Pubkey> = pools
+        .iter()
+        .map(|(_, amm_info)| {
+            if amm_info.coin_vault_mint == *mint {
+                amm_info.coin_vault
+            } else {
+                amm_info.pc_vault
+            }
+        })
+        .collect();
+    let liquidity: Vec<u64> = get_multiple_accounts(client, &vaults)?
+        .into_iter()
+        .map(|account| {
+            account
+                .and_then(|account| Account::unpack(&account.data).ok())
+                .map_or(0, |vault| vault.amount)
+        })
+        .collect();
+    let mut sorted: Vec<(u64, (Pubkey, AmmInfo))> =
+        liquidity.into_iter().zip(pools.into_iter()).collect();
+    sorted.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(sorted.into_iter().map(|(_, pool)| pool).collect())
+}
+
+// pools trading mint on either side, sorted by their mint reserve. one
+// program scan per side
+pub fn find_pools_for_mint(
+    client: &RpcClient,
+    amm_program: &Pubkey,
+    mint: &Pubkey,
+) -> Result<Vec<(Pubkey, AmmInfo)>> {
+    let mut pools = find_pools(client, amm_program, &[(AmmMintField::Coin, mint)])?;
+    pools.extend(find_pools(
+        client,
+        amm_program,
+        &[(AmmMintField::Pc, mint)],
+    )?);
+    sort_by_liquidity(client, pools, mint)
+}
+
+// pools trading a against b in either order, sorted by their reserve of a.
+// memcmp filters are all required, so each order is its own program scan
+pub fn find_pools_for_pair(
+    client: &RpcClient,
+    amm_program: &Pubkey,
+    a: &Pubkey,
+    b: &Pubkey,
+) -> Result<Vec<(Pubkey, AmmInfo)>> {
+    let mut pools = find_pools(
+        client,
+        amm_program,
+        &[(AmmMintField::Coin, a), (AmmMintField::Pc, b)],
+    )?;
+    pools.extend(find_pools(
+        client,
+        amm_program,
+        &[(AmmMintField::Coin, b), (AmmMintField::Pc, a)],
+    )?);
+    sort_by_liquidity(client, pools, a)
+}
+
+// the pool that issues the lp mint
+pub fn find_pool_for_lp_mint(
+    client: &RpcClient,
+    amm_program: &Pubkey,
+    lp_mint: &Pubkey,
+) -> Result<Option<(Pubkey, AmmInfo)>> {
+    Ok(
+        find_pools(client, amm_program, &[(AmmMintField::Lp, lp_mint)])?
+            .into_iter()
+            .next(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the layout the memcmp filters run against on chain
+    #[test]
+    fn amm_mint_field_offsets_match_the_amm_layout() {
+        assert_eq!(size_of::<AmmInfo>(), 752);
+        assert_eq!(AmmMintField::Coin.offset(), 400);
+        assert_eq!(AmmMintField::Pc.offset(), 432);
+        assert_eq!(AmmMintField::Lp.offset(), 464);
+    }
+
+    #[test]
+    fn amm_mint_field_offsets_read_back_the_mints() {
+        let mut data = vec![0u8; size_of::<AmmInfo>()];
+        let mints = [
+            (AmmMintField::Coin, Pubkey::new_unique()),
+            (AmmMintField::Pc, Pubkey::new_unique()),
+            (AmmMintField::Lp, Pubkey::new_unique()),
+        ];
+        for (field, mint) in mints.iter() {
+            data[field.offset()..field.offset() + 32].copy_from_slice(mint.as_ref());
+        }
+        let amm = deserialize_account::<AmmInfo>(&data).unwrap();
+        assert_eq!(amm.coin_vault_mint, mints[0].1);
+        assert_eq!(amm.pc_vault_mint, mints[1].1);
+        assert_eq!(amm.lp_mint, mints[2].1);
+    }
+}
@@ -0,0 +1,355 @@
+use anyhow::{format_err, Result};
+use solana_client::{
+    rpc_client::RpcClient, rpc_config::RpcSimulateTransactionConfig,
+    rpc_response::RpcSimulateTransactionResult,
+};
+use solana_sdk::{
+    address_lookup_table_account::AddressLookupTableAccount, commitment_config::CommitmentConfig,
+    hash::Hash, instruction::Instruction, pubkey::Pubkey, transaction::VersionedTransaction,
+};
+
+use crate::amm_math::{quote_swap, PoolState, TEN_THOUSAND};
+use crate::split::pool_swap_direction;
+use crate::transaction::{
+    build_swap_instructions_with_min_out, build_unsigned_swap_transaction,
+    build_unsigned_v0_transaction, SwapTxConfig, UnsignedSwapTx,
+};
+
+// most a transaction may request
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+#[derive(Clone, Debug)]
+pub struct PriorityFeeConfig {
+    /// percentile of the recent fees paid on the writable accounts, 0 to 100
+    pub percentile: u8,
+    /// added on top of the simulated units
+    pub compute_unit_margin_bps: u64,
+    /// micro lamports per compute unit never exceeded
+    pub max_compute_unit_price: Option<u64>,
+}
+
+impl Default for PriorityFeeConfig {
+    fn default() -> Self {
+        PriorityFeeConfig {
+            percentile: 75,
+            compute_unit_margin_bps: 1000,
+            max_compute_unit_price: None,
+        }
+    }
+}
+
+// accounts a raydium swap writes, the ones priority fees are paid against
+pub fn swap_writable_accounts(pool: &PoolState) -> Vec<Pubkey> {
+    let amm_keys = &pool.pool_amm_keys;
+    let market_keys = &pool.pool_market_keys;
+    vec![
+        amm_keys.amm_pool,
+        amm_keys.amm_open_order,
+        amm_keys.amm_coin_vault,
+        amm_keys.amm_pc_vault,
+        amm_keys.market,
+        *market_keys.bids,
+        *market_keys.asks,
+        *market_keys.event_q,
+        *market_keys.coin_vault,
+        *market_keys.pc_vault,
+    ]
+}
+
+// unsigned, the rpc swaps in the latest blockhash
+pub fn simulation_transaction(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<VersionedTransaction> {
+    build_unsigned_v0_transaction(payer, instructions, lookup_tables, Hash::default())
+}
+
+pub fn simulation_config() -> RpcSimulateTransactionConfig {
+    RpcSimulateTransactionConfig {
+        sig_verify: false,
+        replace_recent_blockhash: true,
+        commitment: Some(CommitmentConfig::confirmed()),
+        ..RpcSimulateTransactionConfig::default()
+    }
+}
+
+pub fn simulated_units(result: RpcSimulateTransactionResult) -> Result<u64> {
+    if let Some(err) = result.err {
+        return Err(format_err!(
+            "simulation failed: {}, logs: {:?}",
+            err,
+            result.logs.unwrap_or_default()
+        ));
+    }
+    result
+        .units_consumed
+        .ok_or_else(|| format_err!("simulation returned no units consumed"))
+}
+
+// units consumed by the instructions, simulated without signatures against the
+// latest blockhash. the compute budget instructions in the list should not cap
+// the simulation, so set their limit to MAX_COMPUTE_UNIT_LIMIT
+pub fn simulate_compute_units(
+    client: &RpcClient,
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<u64> {
+    let transaction = simulation_transaction(payer, instructions, lookup_tables)?;
+    simulated_units(
+        client
+            .simulate_transaction_with_config(&transaction, simulation_config())?
+            .value,
+    )
+}
+
+pub fn compute_unit_limit_with_margin(units_consumed: u64, margin_bps: u64) -> u32 {
+    let limit = (units_consumed as u128 * (TEN_THOUSAND + margin_bps) as u128
+        + (TEN_THOUSAND - 1) as u128)
+        / TEN_THOUSAND as u128;
+    limit.min(MAX_COMPUTE_UNIT_LIMIT as u128) as u32
+}
+
+// nearest rank percentile, 0 without any fees
+pub fn percentile_fee(mut fees: Vec<u64>, percentile: u8) -> Result<u64> {
+    if percentile > 100 {
+        return Err(format_err!("percentile must not exceed 100"));
+    }
+    if fees.is_empty() {
+        return Ok(0);
+    }
+    fees.sort_unstable();
+    let rank = (fees.len() * percentile as usize + 99) / 100;
+    Ok(fees[rank.max(1) - 1])
+}
+
+// micro lamports per compute unit, nearest rank percentile of the recent fees
+pub fn recent_priority_fee(
+    client: &RpcClient,
+    writable_accounts: &[Pubkey],
+    percentile: u8,
+) -> Result<u64> {
+    percentile_fee(
+        client
+            .get_recent_prioritization_fees(writable_accounts)?
+            .iter()
+            .map(|fee| fee.prioritization_fee)
+            .collect(),
+        percentile,
+    )
+}
+
+// the swap with its limit raised to MAX_COMPUTE_UNIT_LIMIT, so the simulation
+// is not cut short
+pub fn swap_simulation_instructions(
+    amm_program: &Pubkey,
+    pool: &PoolState,
+    user_owner: &Pubkey,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    min_amount_out: u64,
+    config: &SwapTxConfig,
+) -> Result<Vec<Instruction>> {
+    build_swap_instructions_with_min_out(
+        amm_program,
+        pool,
+        user_owner,
+        input_mint,
+        amount_in,
+        min_amount_out,
+        &SwapTxConfig {
+            compute_unit_limit: MAX_COMPUTE_UNIT_LIMIT,
+            ..config.clone()
+        },
+    )
+}
+
+pub fn capped_compute_unit_price(fee_config: &PriorityFeeConfig, compute_unit_price: u64) -> u64 {
+    match fee_config.max_compute_unit_price {
+        Some(max_compute_unit_price) => compute_unit_price.min(max_compute_unit_price),
+        None => compute_unit_price,
+    }
+}
+
+pub fn priced_swap_tx_config(
+    config: &SwapTxConfig,
+    fee_config: &PriorityFeeConfig,
+    units_consumed: u64,
+    compute_unit_price: u64,
+) -> SwapTxConfig {
+    SwapTxConfig {
+        compute_unit_limit: compute_unit_limit_with_margin(
+            units_consumed,
+            fee_config.compute_unit_margin_bps,
+        ),
+        compute_unit_price: capped_compute_unit_price(fee_config, compute_unit_price),
+        ..config.clone()
+    }
+}
+
+// min out of a base in swap quoted on the given state
+pub fn quoted_min_amount_out(
+    pool: &PoolState,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    slippage_bps: u64,
+) -> Result<u64> {
+    let swap_direction = pool_swap_direction(pool, input_mint)?;
+    let quote = quote_swap(&pool.pool, swap_direction, amount_in, true, slippage_bps)?;
+    Ok(quote.other_amount_threshold)
+}
+
+// simulates the swap, then returns config with a tight limit and a price picked
+// from the recent fees on the pool accounts
+pub fn estimate_swap_tx_config(
+    client: &RpcClient,
+    amm_program: &Pubkey,
+    pool: &PoolState,
+    user_owner: &Pubkey,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    slippage_bps: u64,
+    config: &SwapTxConfig,
+    fee_config: &PriorityFeeConfig,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<SwapTxConfig> {
+    estimate_swap_tx_config_with_min_out(
+        client,
+        amm_program,
+        pool,
+        user_owner,
+        input_mint,
+        amount_in,
+        quoted_min_amount_out(pool, input_mint, amount_in, slippage_bps)?,
+        config,
+        fee_config,
+        lookup_tables,
+    )
+}
+
+// same for a swap that fails below min_amount_out
+pub fn estimate_swap_tx_config_with_min_out(
+    client: &RpcClient,
+    amm_program: &Pubkey,
+    pool: &PoolState,
+    user_owner: &Pubkey,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    min_amount_out: u64,
+    config: &SwapTxConfig,
+    fee_config: &PriorityFeeConfig,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<SwapTxConfig> {
+    let instructions = swap_simulation_instructions(
+        amm_program,
+        pool,
+        user_owner,
+        input_mint,
+        amount_in,
+        min_amount_out,
+        config,
+    )?;
+    let units_consumed = simulate_compute_units(client, user_owner, &instructions, lookup_tables)?;
+    let compute_unit_price =
+        recent_priority_fee(client, &swap_writable_accounts(pool), fee_config.percentile)?;
+    Ok(priced_swap_tx_config(
+        config,
+        fee_config,
+        units_consumed,
+        compute_unit_price,
+    ))
+}
+
+pub fn build_swap_transaction_with_priority_fee(
+    client: &RpcClient,
+    amm_program: &Pubkey,
+    pool: &PoolState,
+    user_owner: &Pubkey,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    slippage_bps: u64,
+    config: &SwapTxConfig,
+    fee_config: &PriorityFeeConfig,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<UnsignedSwapTx> {
+    let config = estimate_swap_tx_config(
+        client,
+        amm_program,
+        pool,
+        user_owner,
+        input_mint,
+        amount_in,
+        slippage_bps,
+        config,
+        fee_config,
+        lookup_tables,
+    )?;
+    build_unsigned_swap_transaction(
+        amm_program,
+        pool,
+        user_owner,
+        input_mint,
+        amount_in,
+        slippage_bps,
+        &config,
+        lookup_tables,
+        client.get_latest_blockhash()?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute_unit_limit_with_margin_rounds_up() {
+        assert_eq!(compute_unit_limit_with_margin(100_000, 1000), 110_000);
+        assert_eq!(compute_unit_limit_with_margin(33_333, 1000), 36_667);
+        assert_eq!(compute_unit_limit_with_margin(50_000, 0), 50_000);
+    }
+
+    #[test]
+    fn compute_unit_limit_with_margin_is_capped() {
+        assert_eq!(
+            compute_unit_limit_with_margin(1_300_000, 1000),
+            MAX_COMPUTE_UNIT_LIMIT
+        );
+        assert_eq!(
+            compute_unit_limit_with_margin(u64::MAX, 10_000),
+            MAX_COMPUTE_UNIT_LIMIT
+        );
+    }
+
+    #[test]
+    fn percentile_fee_takes_the_nearest_rank() {
+        let fees: Vec<u64> = vec![10, 1, 9, 2, 8, 3, 7, 4, 6, 5];
+        assert_eq!(percentile_fee(fees.clone(), 0).unwrap(), 1);
+        assert_eq!(percentile_fee(fees.clone(), 50).unwrap(), 5);
+        assert_eq!(percentile_fee(fees.clone(), 75).unwrap(), 8);
+        assert_eq!(percentile_fee(fees.clone(), 100).unwrap(), 10);
+        assert_eq!(percentile_fee(vec![7], 75).unwrap(), 7);
+    }
+
+    #[test]
+    fn percentile_fee_without_fees_is_zero() {
+        assert_eq!(percentile_fee(Vec::new(), 75).unwrap(), 0);
+    }
+
+    #[test]
+    fn percentile_fee_rejects_percentiles_above_100() {
+        assert!(percentile_fee(vec![1], 101).is_err());
+    }
+
+    #[test]
+    fn priced_swap_tx_config_caps_the_price() {
+        let fee_config = PriorityFeeConfig {
+            max_compute_unit_price: Some(5_000),
+            ..PriorityFeeConfig::default()
+        };
+        let config = priced_swap_tx_config(&SwapTxConfig::default(), &fee_config, 80_000, 9_000);
+        assert_eq!(config.compute_unit_limit, 88_000);
+        assert_eq!(config.compute_unit_price, 5_000);
+        assert!(config.wrap_unwrap_sol);
+    }
+}
@@ -0,0 +1,382 @@
+use anyhow::{format_err, Result};
+use solana_client::rpc_client::RpcClient;
+use solana_program::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use spl_token::state::Account;
+use std::str::FromStr;
+
+use crate::amm_math::{PoolState, TEN_THOUSAND};
+use crate::rpc::get_multiple_accounts;
+use crate::token::{decode_mint, MintInfo};
+
+#[derive(Clone, Debug)]
+pub struct RiskPolicy {
+    pub require_revoked_mint_authority: bool,
+    pub require_revoked_freeze_authority: bool,
+    /// how many of the largest holders count towards concentration
+    pub top_holders: usize,
+    /// pool vaults are not counted as holders
+    pub max_top_holders_share_bps: Option<u64>,
+    pub min_lp_burned_bps: Option<u64>,
+    pub max_creator_lp_share_bps: Option<u64>,
+}
+
+impl Default for RiskPolicy {
+    fn default() -> Self {
+        RiskPolicy {
+            require_revoked_mint_authority: true,
+            require_revoked_freeze_authority: true,
+            top_holders: 10,
+            max_top_holders_share_bps: Some(3000),
+            min_lp_burned_bps: None,
+            max_creator_lp_share_bps: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RiskCheck {
+    pub name: &'static str,
+    pub passed: bool,
+    /// false when the policy only reports the check
+    pub enforced: bool,
+    pub detail: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenRiskReport {
+    pub mint: Pubkey,
+    pub supply: u64,
+    pub decimals: u8,
+    pub mint_authority: Option<Pubkey>,
+    pub freeze_authority: Option<Pubkey>,
+    /// share of the supply held by the largest holders outside the pool
+    pub top_holders_share_bps: u64,
+    pub lp_supply: u64,
+    /// share of the pool lp burned rather than withdrawn
+    pub lp_burned_bps: u64,
+    /// only known when the creator was given
+    pub creator_lp_share_bps: Option<u64>,
+    pub checks: Vec<RiskCheck>,
+}
+
+impl TokenRiskReport {
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.passed || !check.enforced)
+    }
+
+    pub fn failed_checks(&self) -> Vec<&RiskCheck> {
+        self.checks
+            .iter()
+            .filter(|check| check.enforced && !check.passed)
+            .collect()
+    }
+}
+
+fn share_bps(amount: u64, total: u64) -> u64 {
+    if total == 0 {
+        return 0;
+    }
+    (amount as u128 * TEN_THOUSAND as u128 / total as u128).min(TEN_THOUSAND as u128) as u64
+}
+
+// (address, amount) of the largest token accounts of the mint
+fn largest_accounts(client: &RpcClient, mint: &Pubkey) -> Result<Vec<(Pubkey, u64)>> {
+    client
+        .get_token_largest_accounts(mint)?
+        .iter()
+        .map(|balance| {
+            Ok((
+                Pubkey::from_str(&balance.address)?,
+                balance.amount.amount.parse::<u64>()?,
+            ))
+        })
+        .collect()
+}
+
+// the top largest holders outside pool_accounts, as a share of supply
+fn top_holders_share_bps(
+    largest: &[(Pubkey, u64)],
+    pool_accounts: &[Pubkey],
+    top_holders: usize,
+    supply: u64,
+) -> u64 {
+    let held: u64 = largest
+        .iter()
+        .filter(|(address, _)| !pool_accounts.contains(address))
+        .take(top_holders)
+        .map(|(_, amount)| *amount)
+        .sum();
+    share_bps(held, supply)
+}
+
+// withdrawals burn lp and lower lp_amount too, plain burns only lower the supply
+fn lp_burned_bps(pool_lp_amount: u64, lp_supply: u64) -> u64 {
+    share_bps(pool_lp_amount.saturating_sub(lp_supply), pool_lp_amount)
+}
+
+fn check(name: &'static str, passed: bool, enforced: bool, detail: String) -> RiskCheck {
+    RiskCheck {
+        name,
+        passed,
+        enforced,
+        detail,
+    }
+}
+
+fn risk_checks(
+    policy: &RiskPolicy,
+    mint_authority: Option<Pubkey>,
+    freeze_authority: Option<Pubkey>,
+    top_holders_share_bps: u64,
+    lp_burned_bps: u64,
+    creator_lp_share_bps: Option<u64>,
+) -> Vec<RiskCheck> {
+    let mut checks = vec![
+        check(
+            "mint_authority",
+            mint_authority.is_none(),
+            policy.require_revoked_mint_authority,
+            format!("{:?}", mint_authority),
+        ),
+        check(
+            "freeze_authority",
+            freeze_authority.is_none(),
+            policy.require_revoked_freeze_authority,
+            format!("{:?}", freeze_authority),
+        ),
+        check(
+            "top_holders",
+            policy
+                .max_top_holders_share_bps
+                .map_or(true, |max| top_holders_share_bps <= max),
+            policy.max_top_holders_share_bps.is_some(),
+            format!(
+                "top {} hold {} bps",
+                policy.top_holders, top_holders_share_bps
+            ),
+        ),
+        check(
+            "lp_burned",
+            policy
+                .min_lp_burned_bps
+                .map_or(true, |min| lp_burned_bps >= min),
+            policy.min_lp_burned_bps.is_some(),
+            format!("{} bps burned", lp_burned_bps),
+        ),
+    ];
+    if let Some(creator_lp_share_bps) = creator_lp_share_bps {
+        checks.push(check(
+            "creator_lp",
+            policy
+                .max_creator_lp_share_bps
+                .map_or(true, |max| creator_lp_share_bps <= max),
+            policy.max_creator_lp_share_bps.is_some(),
+            format!("creator holds {} bps", creator_lp_share_bps),
+        ));
+    }
+    checks
+}
+
+// mint is the pool coin or pc mint to check, creator the wallet that seeded the pool.
+// load_state only accepts spl token vaults, so both pool mints are spl token mints
+pub fn build_token_risk_report(
+    client: &RpcClient,
+    pool: &PoolState,
+    mint: &Pubkey,
+    creator: Option<&Pubkey>,
+    policy: &RiskPolicy,
+) -> Result<TokenRiskReport> {
+    let keys = &pool.pool_amm_keys;
+    if *mint != keys.amm_coin_mint && *mint != keys.amm_pc_mint {
+        return Err(format_err!(
+            "pool {} does not trade {}",
+            keys.amm_pool,
+            mint
+        ));
+    }
+    let accounts = get_multiple_accounts(
+        client,
+        &[keys.amm_coin_mint, keys.amm_pc_mint, keys.amm_lp_mint],
+    )?;
+    let (coin_mint_account, pc_mint_account, lp_mint_account) =
+        match (&accounts[0], &accounts[1], &accounts[2]) {
+            (Some(coin), Some(pc), Some(lp)) => (coin, pc, lp),
+            _ => return Err(format_err!("pool {} mint accounts missing", keys.amm_pool)),
+        };
+    let coin_mint = decode_mint(&keys.amm_coin_mint, coin_mint_account)?;
+    let pc_mint = decode_mint(&keys.amm_pc_mint, pc_mint_account)?;
+    let lp_mint = decode_mint(&keys.amm_lp_mint, lp_mint_account)?;
+    let token_mint: &MintInfo = if *mint == keys.amm_coin_mint {
+        &coin_mint
+    } else {
+        &pc_mint
+    };
+
+    // vaults hold the pool liquidity, not a holder position
+    let pool_accounts = [
+        keys.amm_coin_vault,
+        keys.amm_pc_vault,
+        *pool.pool_market_keys.coin_vault,
+        *pool.pool_market_keys.pc_vault,
+    ];
+    let top_holders_share_bps = top_holders_share_bps(
+        &largest_accounts(client, mint)?,
+        &pool_accounts,
+        policy.top_holders,
+        token_mint.supply,
+    );
+    let lp_burned_bps = lp_burned_bps(pool.pool.pool_lp_amount, lp_mint.supply);
+    let creator_lp_share_bps = match creator {
+        Some(creator) => {
+            let lp_accounts = largest_accounts(client, &keys.amm_lp_mint)?;
+            let addresses: Vec<Pubkey> = lp_accounts.iter().map(|(address, _)| *address).collect();
+            let mut creator_lp = 0u64;
+            for (account, (_, amount)) in get_multiple_accounts(client, &addresses)?
+                .iter()
+                .zip(lp_accounts.iter())
+            {
+                if let Some(account) = account {
+                    if Account::unpack(&account.data)?.owner == *creator {
+                        creator_lp += amount;
+                    }
+                }
+            }
+            Some(share_bps(creator_lp, lp_mint.supply))
+        }
+        None => None,
+    };
+    let mint_authority = token_mint.mint_authority;
+    let freeze_authority = token_mint.freeze_authority;
+    let checks = risk_checks(
+        policy,
+        mint_authority,
+        freeze_authority,
+        top_holders_share_bps,
+        lp_burned_bps,
+        creator_lp_share_bps,
+    );
+
+    Ok(TokenRiskReport {
+        mint: *mint,
+        supply: token_mint.supply,
+        decimals: token_mint.decimals,
+        mint_authority,
+        freeze_authority,
+        top_holders_share_bps,
+        lp_supply: lp_mint.supply,
+        lp_burned_bps,
+        creator_lp_share_bps,
+        checks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(checks: Vec<RiskCheck>) -> TokenRiskReport {
+        TokenRiskReport {
+            mint: Pubkey::new_unique(),
+            supply: 1_000,
+            decimals: 6,
+            mint_authority: None,
+            freeze_authority: None,
+            top_holders_share_bps: 0,
+            lp_supply: 0,
+            lp_burned_bps: 0,
+            creator_lp_share_bps: None,
+            checks,
+        }
+    }
+
+    fn find<'a>(checks: &'a [RiskCheck], name: &str) -> &'a RiskCheck {
+        checks.iter().find(|check| check.name == name).unwrap()
+    }
+
+    #[test]
+    fn lp_burned_is_the_pool_lp_no_longer_in_supply() {
+        assert_eq!(lp_burned_bps(1_000, 1_000), 0);
+        assert_eq!(lp_burned_bps(1_000, 250), 7_500);
+        assert_eq!(lp_burned_bps(1_000, 0), TEN_THOUSAND);
+        // supply above the pool amount means nothing was burned
+        assert_eq!(lp_burned_bps(1_000, 1_500), 0);
+        assert_eq!(lp_burned_bps(0, 0), 0);
+    }
+
+    #[test]
+    fn top_holders_leave_out_the_pool_accounts() {
+        let vault = Pubkey::new_unique();
+        let largest = vec![
+            (vault, 5_000),
+            (Pubkey::new_unique(), 1_000),
+            (Pubkey::new_unique(), 500),
+            (Pubkey::new_unique(), 100),
+        ];
+        assert_eq!(top_holders_share_bps(&largest, &[vault], 2, 10_000), 1_500);
+        assert_eq!(top_holders_share_bps(&largest, &[vault], 10, 10_000), 1_600);
+        assert_eq!(top_holders_share_bps(&largest, &[], 1, 10_000), 5_000);
+        assert_eq!(top_holders_share_bps(&largest, &[vault], 2, 0), 0);
+    }
+
+    #[test]
+    fn authorities_fail_only_when_set() {
+        let policy = RiskPolicy::default();
+        let revoked = risk_checks(&policy, None, None, 0, 0, None);
+        assert!(find(&revoked, "mint_authority").passed);
+        assert!(find(&revoked, "freeze_authority").passed);
+        assert!(report(revoked).passed());
+
+        let authority = Some(Pubkey::new_unique());
+        let set = risk_checks(&policy, authority, authority, 0, 0, None);
+        assert!(!find(&set, "mint_authority").passed);
+        assert!(!find(&set, "freeze_authority").passed);
+        let set = report(set);
+        assert!(!set.passed());
+        assert_eq!(set.failed_checks().len(), 2);
+    }
+
+    #[test]
+    fn unenforced_checks_only_report() {
+        let policy = RiskPolicy {
+            require_revoked_mint_authority: false,
+            require_revoked_freeze_authority: false,
+            ..RiskPolicy::default()
+        };
+        let authority = Some(Pubkey::new_unique());
+        let checks = risk_checks(&policy, authority, authority, 0, 0, None);
+        assert!(!find(&checks, "mint_authority").passed);
+        assert!(!find(&checks, "mint_authority").enforced);
+        assert!(report(checks).passed());
+    }
+
+    #[test]
+    fn concentration_and_lp_limits_are_inclusive() {
+        let policy = RiskPolicy {
+            max_top_holders_share_bps: Some(3_000),
+            min_lp_burned_bps: Some(9_000),
+            max_creator_lp_share_bps: Some(500),
+            ..RiskPolicy::default()
+        };
+        let at_limits = risk_checks(&policy, None, None, 3_000, 9_000, Some(500));
+        assert_eq!(at_limits.len(), 5);
+        assert!(report(at_limits).passed());
+
+        let over = risk_checks(&policy, None, None, 3_001, 8_999, Some(501));
+        let names: Vec<&str> = report(over)
+            .failed_checks()
+            .iter()
+            .map(|check| check.name)
+            .collect();
+        assert_eq!(names, vec!["top_holders", "lp_burned", "creator_lp"]);
+    }
+
+    #[test]
+    fn creator_check_needs_a_creator() {
+        let checks = risk_checks(&RiskPolicy::default(), None, None, 0, 0, None);
+        assert_eq!(checks.len(), 4);
+        assert!(checks.iter().all(|check| check.name != "creator_lp"));
+    }
+}
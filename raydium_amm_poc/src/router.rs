@@ -0,0 +1,365 @@
+use anyhow::{format_err, Result};
+use solana_sdk::{
+    address_lookup_table_account::AddressLookupTableAccount,
+    hash::Hash,
+    instruction::Instruction,
+    message::Message,
+    pubkey::Pubkey,
+    transaction::{Transaction, VersionedTransaction},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+use spl_token::ID;
+use std::collections::HashMap;
+
+use crate::amm_math::{min_amount_with_slippage, swap_exact_amount, PoolState};
+use crate::instruction::swap;
+use crate::raydium_amm::maths::SwapDirection;
+use crate::transaction::build_unsigned_v0_transaction;
+
+#[derive(Clone, Debug)]
+pub struct RouteHop {
+    /// index into Router::pools
+    pub pool_index: usize,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub swap_direction: SwapDirection,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub hops: Vec<RouteHop>,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+// pools indexed by both of their mints
+pub struct Router {
+    pools: Vec<PoolState>,
+    pools_by_mint: HashMap<Pubkey, Vec<usize>>,
+}
+
+impl Router {
+    pub fn new(pools: Vec<PoolState>) -> Self {
+        let mut pools_by_mint: HashMap<Pubkey, Vec<usize>> = HashMap::new();
+        for (index, pool) in pools.iter().enumerate() {
+            pools_by_mint
+                .entry(pool.pool_amm_keys.amm_coin_mint)
+                .or_default()
+                .push(index);
+            pools_by_mint
+                .entry(pool.pool_amm_keys.amm_pc_mint)
+                .or_default()
+                .push(index);
+        }
+        Router {
+            pools,
+            pools_by_mint,
+        }
+    }
+
+    pub fn pools(&self) -> &[PoolState] {
+        &self.pools
+    }
+
+    pub fn pools_for_mint(&self, mint: &Pubkey) -> &[usize] {
+        self.pools_by_mint
+            .get(mint)
+            .map(|pools| pools.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn quote_hop(
+        &self,
+        pool_index: usize,
+        input_mint: &Pubkey,
+        amount_in: u64,
+    ) -> Result<RouteHop> {
+        let pool = &self.pools[pool_index];
+        let (swap_direction, output_mint) = if *input_mint == pool.pool_amm_keys.amm_coin_mint {
+            (SwapDirection::Coin2PC, pool.pool_amm_keys.amm_pc_mint)
+        } else {
+            (SwapDirection::PC2Coin, pool.pool_amm_keys.amm_coin_mint)
+        };
+        let amount_out = swap_exact_amount(
+            pool.pool.pool_pc_vault_amount,
+            pool.pool.pool_coin_vault_amount,
+            pool.pool.swap_fee_numerator,
+            pool.pool.swap_fee_denominator,
+            swap_direction,
+            amount_in,
+            true,
+        )?;
+        Ok(RouteHop {
+            pool_index,
+            input_mint: *input_mint,
+            output_mint,
+            swap_direction,
+            amount_in,
+            amount_out,
+        })
+    }
+
+    // every 1-hop and 2-hop route, best output first
+    pub fn find_routes(
+        &self,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+        amount_in: u64,
+    ) -> Vec<Route> {
+        let mut routes: Vec<Route> = Vec::new();
+        let first_pools = match self.pools_by_mint.get(input_mint) {
+            Some(first_pools) => first_pools,
+            None => return routes,
+        };
+        for &first in first_pools {
+            let first_hop = match self.quote_hop(first, input_mint, amount_in) {
+                Ok(hop) if hop.amount_out > 0 => hop,
+                _ => continue,
+            };
+            if first_hop.output_mint == *output_mint {
+                routes.push(Route {
+                    amount_in,
+                    amount_out: first_hop.amount_out,
+                    hops: vec![first_hop],
+                });
+                continue;
+            }
+            let second_pools = match self.pools_by_mint.get(&first_hop.output_mint) {
+                Some(second_pools) => second_pools,
+                None => continue,
+            };
+            for &second in second_pools {
+                if second == first {
+                    continue;
+                }
+                let second_hop =
+                    match self.quote_hop(second, &first_hop.output_mint, first_hop.amount_out) {
+                        Ok(hop) if hop.output_mint == *output_mint => hop,
+                        _ => continue,
+                    };
+                routes.push(Route {
+                    amount_in,
+                    amount_out: second_hop.amount_out,
+                    hops: vec![first_hop.clone(), second_hop],
+                });
+            }
+        }
+        routes.sort_by(|a, b| {
+            b.amount_out
+                .cmp(&a.amount_out)
+                .then(a.hops.len().cmp(&b.hops.len()))
+        });
+        routes
+    }
+
+    pub fn best_route(
+        &self,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+        amount_in: u64,
+    ) -> Option<Route> {
+        self.find_routes(input_mint, output_mint, amount_in)
+            .into_iter()
+            .next()
+    }
+
+    // each hop spends the amount the previous hop was quoted to return and only
+    // the last hop carries the slippage. an intermediate hop has to return its
+    // full quote, anything less could not fund the next hop
+    pub fn build_route_instructions(
+        &self,
+        amm_program: &Pubkey,
+        route: &Route,
+        user_owner: &Pubkey,
+        slippage_bps: u64,
+    ) -> Result<Vec<Instruction>> {
+        let last = match route.hops.len().checked_sub(1) {
+            Some(last) => last,
+            None => return Err(format_err!("route has no hops")),
+        };
+        let mut amount_in = route.amount_in;
+        for hop in route.hops.iter() {
+            if hop.amount_in != amount_in {
+                return Err(format_err!(
+                    "hop through pool {} spends {}, the route reaches it with {}",
+                    hop.pool_index,
+                    hop.amount_in,
+                    amount_in
+                ));
+            }
+            amount_in = hop.amount_out;
+        }
+        let mut instructions: Vec<Instruction> = Vec::new();
+        for hop in route.hops.iter() {
+            instructions.push(create_associated_token_account_idempotent(
+                user_owner,
+                user_owner,
+                &hop.output_mint,
+                &ID,
+            ));
+        }
+        for (index, hop) in route.hops.iter().enumerate() {
+            let pool = &self.pools[hop.pool_index];
+            let min_amount_out = if index == last {
+                min_amount_with_slippage(hop.amount_out, slippage_bps)
+            } else {
+                hop.amount_out
+            };
+            instructions.push(swap(
+                amm_program,
+                &pool.pool_amm_keys,
+                &pool.pool_market_keys,
+                user_owner,
+                &get_associated_token_address(user_owner, &hop.input_mint),
+                &get_associated_token_address(user_owner, &hop.output_mint),
+                hop.amount_in,
+                min_amount_out,
+                true,
+            )?);
+        }
+        Ok(instructions)
+    }
+
+    pub fn build_route_transaction(
+        &self,
+        amm_program: &Pubkey,
+        route: &Route,
+        user_owner: &Pubkey,
+        slippage_bps: u64,
+        recent_blockhash: Hash,
+    ) -> Result<Transaction> {
+        let instructions =
+            self.build_route_instructions(amm_program, route, user_owner, slippage_bps)?;
+        let mut message = Message::new(&instructions, Some(user_owner));
+        message.recent_blockhash = recent_blockhash;
+        Ok(Transaction::new_unsigned(message))
+    }
+
+    // two hops list too many accounts for a legacy message, pass the pool
+    // lookup tables to fit them
+    pub fn build_route_v0_transaction(
+        &self,
+        amm_program: &Pubkey,
+        route: &Route,
+        user_owner: &Pubkey,
+        slippage_bps: u64,
+        lookup_tables: &[AddressLookupTableAccount],
+        recent_blockhash: Hash,
+    ) -> Result<VersionedTransaction> {
+        let instructions =
+            self.build_route_instructions(amm_program, route, user_owner, slippage_bps)?;
+        build_unsigned_v0_transaction(user_owner, &instructions, lookup_tables, recent_blockhash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::pool_state;
+    use arrayref::array_ref;
+
+    // (amount_in, min_amount_out) of a swap base in instruction
+    fn swap_amounts(instruction: &Instruction) -> (u64, u64) {
+        let data = &instruction.data;
+        (
+            u64::from_le_bytes(*array_ref![data, 1, 8]),
+            u64::from_le_bytes(*array_ref![data, 9, 8]),
+        )
+    }
+
+    // input -> mid in the first pool, mid -> output in the second
+    fn two_hop_router() -> (Router, Pubkey, Pubkey, Pubkey) {
+        let (input, mid, output) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let router = Router::new(vec![
+            pool_state(input, mid, 1_000_000, 2_000_000),
+            pool_state(output, mid, 3_000_000, 2_000_000),
+        ]);
+        (router, input, mid, output)
+    }
+
+    #[test]
+    fn find_routes_chains_the_hop_amounts() {
+        let (router, input, mid, output) = two_hop_router();
+        let route = router.best_route(&input, &output, 10_000).unwrap();
+        assert_eq!(route.hops.len(), 2);
+        assert_eq!(route.hops[0].input_mint, input);
+        assert_eq!(route.hops[0].output_mint, mid);
+        assert_eq!(route.hops[0].amount_in, 10_000);
+        assert_eq!(route.hops[1].amount_in, route.hops[0].amount_out);
+        assert_eq!(route.hops[1].output_mint, output);
+        assert_eq!(route.amount_out, route.hops[1].amount_out);
+    }
+
+    #[test]
+    fn build_route_instructions_applies_slippage_on_the_last_hop_only() {
+        let (router, input, mid, output) = two_hop_router();
+        let (amm_program, user) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let route = router.best_route(&input, &output, 10_000).unwrap();
+        let instructions = router
+            .build_route_instructions(&amm_program, &route, &user, 100)
+            .unwrap();
+        assert_eq!(instructions.len(), 4);
+
+        // the destination atas first, in hop order
+        for (instruction, mint) in instructions[..2].iter().zip([mid, output].iter()) {
+            assert_eq!(instruction.program_id, spl_associated_token_account::id());
+            assert_eq!(
+                instruction.accounts[1].pubkey,
+                get_associated_token_address(&user, mint)
+            );
+        }
+
+        let (first, second) = (&instructions[2], &instructions[3]);
+        assert_eq!(first.program_id, amm_program);
+        assert_eq!(
+            first.accounts[1].pubkey,
+            router.pools()[0].pool_amm_keys.amm_pool
+        );
+        assert_eq!(
+            second.accounts[1].pubkey,
+            router.pools()[1].pool_amm_keys.amm_pool
+        );
+        let hops = &route.hops;
+        assert_eq!(swap_amounts(first), (10_000, hops[0].amount_out));
+        assert_eq!(
+            swap_amounts(second),
+            (
+                hops[0].amount_out,
+                min_amount_with_slippage(hops[1].amount_out, 100)
+            )
+        );
+        let accounts = |instruction: &Instruction| -> Vec<Pubkey> {
+            instruction
+                .accounts
+                .iter()
+                .map(|meta| meta.pubkey)
+                .collect()
+        };
+        assert!(accounts(first).contains(&get_associated_token_address(&user, &input)));
+        assert!(accounts(first).contains(&get_associated_token_address(&user, &mid)));
+        assert!(accounts(second).contains(&get_associated_token_address(&user, &mid)));
+        assert!(accounts(second).contains(&get_associated_token_address(&user, &output)));
+    }
+
+    #[test]
+    fn build_route_instructions_rejects_hops_that_do_not_chain() {
+        let (router, input, _, output) = two_hop_router();
+        let mut route = router.best_route(&input, &output, 10_000).unwrap();
+        route.hops[1].amount_in += 1;
+        assert!(router
+            .build_route_instructions(&Pubkey::new_unique(), &route, &Pubkey::new_unique(), 100)
+            .is_err());
+        route.hops.clear();
+        assert!(router
+            .build_route_instructions(&Pubkey::new_unique(), &route, &Pubkey::new_unique(), 100)
+            .is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{format_err, Result};
 use solana_client::{
     rpc_client::RpcClient,
     rpc_request::RpcRequest,
@@ -9,28 +9,51 @@ use solana_sdk::{
 };
 
 use solana_transaction_status::UiTransactionEncoding;
+use std::{mem::size_of, ptr};
 
 pub fn get_account<T>(client: &RpcClient, amm_pool_key: &Pubkey) -> Result<Option<T>>
 where
-    T: Clone,
+    T: Copy,
 {
     if let Some(account) = client
         .get_account_with_commitment(amm_pool_key, CommitmentConfig::processed())?
         .value
     {
-        let account_data = account.data.as_slice();
-        let ret = unsafe { &*(&account_data[0] as *const u8 as *const T) };
-        Ok(Some(ret.clone()))
+        Ok(Some(deserialize_account::<T>(account.data.as_slice())?))
     } else {
         Ok(None)
     }
 }
 
+// T is a repr(C) account layout of integers and pubkeys, any bytes are a valid value
+pub(crate) fn deserialize_account<T>(account_data: &[u8]) -> Result<T>
+where
+    T: Copy,
+{
+    if account_data.len() < size_of::<T>() {
+        return Err(format_err!(
+            "account data is {} bytes, expected at least {}",
+            account_data.len(),
+            size_of::<T>()
+        ));
+    }
+    // account data carries no alignment guarantee
+    Ok(unsafe { ptr::read_unaligned(account_data.as_ptr() as *const T) })
+}
+
+// getMultipleAccounts takes at most this many keys
+pub const MULTIPLE_ACCOUNTS_LIMIT: usize = 100;
+
+// any number of keys, fetched in chunks the rpc accepts
 pub fn get_multiple_accounts(
     client: &RpcClient,
     pubkeys: &[Pubkey],
 ) -> Result<Vec<Option<Account>>> {
-    Ok(client.get_multiple_accounts(pubkeys)?)
+    let mut accounts: Vec<Option<Account>> = Vec::with_capacity(pubkeys.len());
+    for chunk in pubkeys.chunks(MULTIPLE_ACCOUNTS_LIMIT) {
+        accounts.extend(client.get_multiple_accounts(chunk)?);
+    }
+    Ok(accounts)
 }
 
 pub fn simulate_transaction(
@@ -39,11 +62,19 @@ pub fn simulate_transaction(
     sig_verify: bool,
     cfg: CommitmentConfig,
 ) -> RpcResult<RpcSimulateTransactionResult> {
-    let serialized_encoded = base64::encode(bincode::serialize(transaction).unwrap());
     client.send(
         RpcRequest::SimulateTransaction,
-        serde_json::json!([serialized_encoded, {
-            "sigVerify": sig_verify, "commitment": cfg.commitment, "encoding": Some(UiTransactionEncoding::Base64)
-        }]),
+        simulate_transaction_params(transaction, sig_verify, cfg),
     )
 }
+
+pub fn simulate_transaction_params(
+    transaction: &Transaction,
+    sig_verify: bool,
+    cfg: CommitmentConfig,
+) -> serde_json::Value {
+    let serialized_encoded = base64::encode(bincode::serialize(transaction).unwrap());
+    serde_json::json!([serialized_encoded, {
+        "sigVerify": sig_verify, "commitment": cfg.commitment, "encoding": Some(UiTransactionEncoding::Base64)
+    }])
+}
@@ -0,0 +1,283 @@
+use anyhow::{format_err, Result};
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+use spl_token::ID;
+
+use crate::amm_math::{min_amount_with_slippage, swap_exact_amount, CalculateResult, PoolState};
+use crate::instruction::swap;
+use crate::raydium_amm::maths::SwapDirection;
+
+#[derive(Clone, Debug)]
+pub struct SplitAllocation {
+    /// index into the pools or states the split was computed for
+    pub pool_index: usize,
+    pub swap_direction: SwapDirection,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct SplitOrder {
+    /// only pools that receive a non zero amount
+    pub allocations: Vec<SplitAllocation>,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+fn swap_out(state: &CalculateResult, swap_direction: SwapDirection, amount_in: u64) -> u64 {
+    if amount_in == 0 {
+        return 0;
+    }
+    swap_exact_amount(
+        state.pool_pc_vault_amount,
+        state.pool_coin_vault_amount,
+        state.swap_fee_numerator,
+        state.swap_fee_denominator,
+        swap_direction,
+        amount_in,
+        true,
+    )
+    .unwrap_or(0)
+}
+
+// out(x) = r_out * g * x / (r_in + g * x), g = 1 - fee
+// equal marginal output on every used pool gives
+// x_i = mu * sqrt(r_out_i * r_in_i / g_i) - r_in_i / g_i
+// with mu = (amount + sum(r_in_i / g_i)) / sum(sqrt(r_out_i * r_in_i / g_i))
+fn split_curves(states: &[(&CalculateResult, SwapDirection)], amount_in: u64) -> Result<Vec<u64>> {
+    let curves: Vec<(f64, f64, f64)> = states
+        .iter()
+        .map(|(state, swap_direction)| {
+            let (reserve_in, reserve_out) = match swap_direction {
+                SwapDirection::Coin2PC => {
+                    (state.pool_coin_vault_amount, state.pool_pc_vault_amount)
+                }
+                SwapDirection::PC2Coin => {
+                    (state.pool_pc_vault_amount, state.pool_coin_vault_amount)
+                }
+            };
+            let g = 1.0 - state.swap_fee_numerator as f64 / state.swap_fee_denominator as f64;
+            (reserve_in as f64, reserve_out as f64, g)
+        })
+        .collect();
+    let mut active: Vec<bool> = curves
+        .iter()
+        .map(|(reserve_in, reserve_out, _)| *reserve_in > 0.0 && *reserve_out > 0.0)
+        .collect();
+    if !active.iter().any(|a| *a) {
+        return Err(format_err!("no pool with liquidity"));
+    }
+
+    if amount_in == 0 {
+        return Ok(vec![0; curves.len()]);
+    }
+
+    let mut shares: Vec<f64> = vec![0.0; curves.len()];
+    while active.iter().any(|a| *a) {
+        let (mut offset, mut weight) = (amount_in as f64, 0.0);
+        for (i, (reserve_in, reserve_out, g)) in curves.iter().enumerate() {
+            if active[i] {
+                offset += reserve_in / g;
+                weight += (reserve_out * reserve_in / g).sqrt();
+            }
+        }
+        let mu = offset / weight;
+        let mut dropped = false;
+        for (i, (reserve_in, reserve_out, g)) in curves.iter().enumerate() {
+            if !active[i] {
+                shares[i] = 0.0;
+                continue;
+            }
+            shares[i] = mu * (reserve_out * reserve_in / g).sqrt() - reserve_in / g;
+            if shares[i] <= 0.0 {
+                active[i] = false;
+                dropped = true;
+            }
+        }
+        if !dropped {
+            break;
+        }
+    }
+
+    // round down, then hand the leftover units to the pool that pays most for them
+    let mut amounts: Vec<u64> = shares
+        .iter()
+        .map(|share| share.max(0.0).min(amount_in as f64) as u64)
+        .collect();
+    let allocated: u64 = amounts.iter().sum();
+    if allocated > amount_in {
+        let best = (0..amounts.len()).max_by_key(|i| amounts[*i]).unwrap();
+        amounts[best] -= allocated - amount_in;
+    } else if allocated < amount_in {
+        let leftover = amount_in - allocated;
+        let best = (0..amounts.len())
+            .filter(|i| curves[*i].0 > 0.0 && curves[*i].1 > 0.0)
+            .max_by_key(|i| {
+                let (state, swap_direction) = states[*i];
+                swap_out(state, swap_direction, amounts[*i] + leftover)
+                    - swap_out(state, swap_direction, amounts[*i])
+            })
+            .unwrap();
+        amounts[best] += leftover;
+    }
+    Ok(amounts)
+}
+
+fn to_split_order(
+    states: &[(&CalculateResult, SwapDirection)],
+    amount_in: u64,
+) -> Result<SplitOrder> {
+    let amounts = split_curves(states, amount_in)?;
+    let allocations: Vec<SplitAllocation> = amounts
+        .iter()
+        .enumerate()
+        .filter(|(_, amount)| **amount > 0)
+        .map(|(pool_index, amount)| {
+            let (state, swap_direction) = states[pool_index];
+            SplitAllocation {
+                pool_index,
+                swap_direction,
+                amount_in: *amount,
+                amount_out: swap_out(state, swap_direction, *amount),
+            }
+        })
+        .collect();
+    Ok(SplitOrder {
+        amount_in,
+        amount_out: allocations.iter().map(|a| a.amount_out).sum(),
+        allocations,
+    })
+}
+
+// all states must have the same coin and pc mints
+pub fn optimize_split(
+    states: &[CalculateResult],
+    swap_direction: SwapDirection,
+    amount_in: u64,
+) -> Result<SplitOrder> {
+    let states: Vec<(&CalculateResult, SwapDirection)> =
+        states.iter().map(|state| (state, swap_direction)).collect();
+    to_split_order(&states, amount_in)
+}
+
+// pools for the same pair may list the mints in either order
+pub fn optimize_pool_split(
+    pools: &[PoolState],
+    input_mint: &Pubkey,
+    amount_in: u64,
+) -> Result<SplitOrder> {
+    let states = pools
+        .iter()
+        .map(|pool| Ok((&pool.pool, pool_swap_direction(pool, input_mint)?)))
+        .collect::<Result<Vec<(&CalculateResult, SwapDirection)>>>()?;
+    to_split_order(&states, amount_in)
+}
+
+pub fn pool_swap_direction(pool: &PoolState, input_mint: &Pubkey) -> Result<SwapDirection> {
+    if *input_mint == pool.pool_amm_keys.amm_coin_mint {
+        Ok(SwapDirection::Coin2PC)
+    } else if *input_mint == pool.pool_amm_keys.amm_pc_mint {
+        Ok(SwapDirection::PC2Coin)
+    } else {
+        Err(format_err!(
+            "pool {} does not trade {}",
+            pool.pool_amm_keys.amm_pool,
+            input_mint
+        ))
+    }
+}
+
+pub fn build_split_instructions(
+    amm_program: &Pubkey,
+    pools: &[PoolState],
+    input_mint: &Pubkey,
+    split: &SplitOrder,
+    user_owner: &Pubkey,
+    slippage_bps: u64,
+) -> Result<Vec<Instruction>> {
+    let first = match split.allocations.first() {
+        Some(first) => &pools[first.pool_index],
+        None => return Err(format_err!("split has no allocations")),
+    };
+    let output_mint = if *input_mint == first.pool_amm_keys.amm_coin_mint {
+        first.pool_amm_keys.amm_pc_mint
+    } else {
+        first.pool_amm_keys.amm_coin_mint
+    };
+    let user_source = get_associated_token_address(user_owner, input_mint);
+    let user_destination = get_associated_token_address(user_owner, &output_mint);
+    let mut instructions: Vec<Instruction> = vec![create_associated_token_account_idempotent(
+        user_owner,
+        user_owner,
+        &output_mint,
+        &ID,
+    )];
+    for allocation in split.allocations.iter() {
+        let pool = &pools[allocation.pool_index];
+        instructions.push(swap(
+            amm_program,
+            &pool.pool_amm_keys,
+            &pool.pool_market_keys,
+            user_owner,
+            &user_source,
+            &user_destination,
+            allocation.amount_in,
+            min_amount_with_slippage(allocation.amount_out, slippage_bps),
+            true,
+        )?);
+    }
+    Ok(instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::pool;
+
+    #[test]
+    fn optimize_split_halves_between_equal_pools() {
+        let states = [pool(1_000_000, 1_000_000), pool(1_000_000, 1_000_000)];
+        let split = optimize_split(&states, SwapDirection::PC2Coin, 100_000).unwrap();
+        assert_eq!(split.allocations.len(), 2);
+        for allocation in split.allocations.iter() {
+            assert!((allocation.amount_in as i64 - 50_000).abs() <= 1);
+        }
+        let amount_in: u64 = split.allocations.iter().map(|a| a.amount_in).sum();
+        assert_eq!(amount_in, 100_000);
+    }
+
+    #[test]
+    fn optimize_split_beats_every_other_split() {
+        let states = [pool(1_000_000, 1_000_000), pool(4_000_000, 4_000_000)];
+        let amount_in = 200_000;
+        let split = optimize_split(&states, SwapDirection::Coin2PC, amount_in).unwrap();
+        for step in 0..=100 {
+            let first = amount_in * step / 100;
+            let amount_out = swap_out(&states[0], SwapDirection::Coin2PC, first)
+                + swap_out(&states[1], SwapDirection::Coin2PC, amount_in - first);
+            assert!(amount_out <= split.amount_out + 1);
+        }
+    }
+
+    #[test]
+    fn optimize_split_skips_pools_with_a_worse_price() {
+        // the second pool pays half as much pc per coin
+        let states = [
+            pool(1_000_000, 1_000_000),
+            pool(500_000, 1_000_000),
+            pool(0, 0),
+        ];
+        let split = optimize_split(&states, SwapDirection::Coin2PC, 10_000).unwrap();
+        assert_eq!(split.allocations.len(), 1);
+        assert_eq!(split.allocations[0].pool_index, 0);
+        assert_eq!(split.allocations[0].amount_in, 10_000);
+    }
+
+    #[test]
+    fn optimize_split_needs_liquidity() {
+        let states = [pool(0, 0)];
+        assert!(optimize_split(&states, SwapDirection::Coin2PC, 10_000).is_err());
+    }
+}
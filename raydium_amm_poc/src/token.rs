@@ -0,0 +1,162 @@
+use anyhow::{format_err, Result};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    state::Mint,
+};
+
+use crate::amm_math::PoolState;
+use crate::rpc::get_multiple_accounts;
+
+#[derive(Clone, Debug)]
+pub struct MintInfo {
+    pub mint: Pubkey,
+    /// spl token or token-2022
+    pub token_program: Pubkey,
+    pub supply: u64,
+    pub decimals: u8,
+    pub mint_authority: Option<Pubkey>,
+    pub freeze_authority: Option<Pubkey>,
+    /// always empty for spl token mints
+    pub extensions: Vec<ExtensionType>,
+}
+
+impl MintInfo {
+    pub fn is_token_2022(&self) -> bool {
+        self.token_program == spl_token_2022::ID
+    }
+
+    pub fn associated_token_address(&self, owner: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(owner, &self.mint, &self.token_program)
+    }
+}
+
+// token-2022 unpacks plain spl token mints too, they are the base layout alone
+pub fn decode_mint(mint: &Pubkey, account: &Account) -> Result<MintInfo> {
+    if account.owner != spl_token::ID && account.owner != spl_token_2022::ID {
+        return Err(format_err!(
+            "{} is owned by {}, not a token program",
+            mint,
+            account.owner
+        ));
+    }
+    let state = StateWithExtensions::<Mint>::unpack(&account.data)
+        .map_err(|e| format_err!("mint {}: {}", mint, e))?;
+    let extensions = if account.owner == spl_token_2022::ID {
+        state
+            .get_extension_types()
+            .map_err(|e| format_err!("mint {} extensions: {}", mint, e))?
+    } else {
+        Vec::new()
+    };
+    Ok(MintInfo {
+        mint: *mint,
+        token_program: account.owner,
+        supply: state.base.supply,
+        decimals: state.base.decimals,
+        mint_authority: state.base.mint_authority.into(),
+        freeze_authority: state.base.freeze_authority.into(),
+        extensions,
+    })
+}
+
+// same order as mints
+pub fn fetch_mint_infos(client: &RpcClient, mints: &[Pubkey]) -> Result<Vec<MintInfo>> {
+    get_multiple_accounts(client, mints)?
+        .iter()
+        .zip(mints.iter())
+        .map(|(account, mint)| match account {
+            Some(account) => decode_mint(mint, account),
+            None => Err(format_err!("mint {} not found", mint)),
+        })
+        .collect()
+}
+
+// (coin, pc) mint infos of the pool
+pub fn fetch_pool_mint_infos(client: &RpcClient, pool: &PoolState) -> Result<(MintInfo, MintInfo)> {
+    let mut infos = fetch_mint_infos(
+        client,
+        &[
+            pool.pool_amm_keys.amm_coin_mint,
+            pool.pool_amm_keys.amm_pc_mint,
+        ],
+    )?;
+    let pc = infos.remove(1);
+    Ok((infos.remove(0), pc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::{program_option::COption, program_pack::Pack};
+    use spl_token_2022::extension::{
+        mint_close_authority::MintCloseAuthority, non_transferable::NonTransferable,
+        StateWithExtensionsMut,
+    };
+
+    fn mint(mint_authority: Pubkey) -> Mint {
+        Mint {
+            mint_authority: COption::Some(mint_authority),
+            supply: 1_000_000,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+    }
+
+    fn account(owner: Pubkey, data: Vec<u8>) -> Account {
+        Account {
+            lamports: 1_000_000,
+            data,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    #[test]
+    fn decode_mint_reads_token_2022_extensions() {
+        let authority = Pubkey::new_unique();
+        let extension_types = [
+            ExtensionType::MintCloseAuthority,
+            ExtensionType::NonTransferable,
+        ];
+        let len = ExtensionType::try_calculate_account_len::<Mint>(&extension_types).unwrap();
+        let mut data = vec![0; len];
+        let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+        state.init_extension::<MintCloseAuthority>(true).unwrap();
+        state.init_extension::<NonTransferable>(true).unwrap();
+        state.base = mint(authority);
+        state.pack_base();
+        state.init_account_type().unwrap();
+
+        let mint_key = Pubkey::new_unique();
+        let info = decode_mint(&mint_key, &account(spl_token_2022::ID, data)).unwrap();
+        assert_eq!(info.mint, mint_key);
+        assert!(info.is_token_2022());
+        assert_eq!((info.supply, info.decimals), (1_000_000, 6));
+        assert_eq!(info.mint_authority, Some(authority));
+        assert_eq!(info.freeze_authority, None);
+        assert_eq!(info.extensions, extension_types.to_vec());
+    }
+
+    #[test]
+    fn decode_mint_reads_spl_token_mints() {
+        let mut data = vec![0; Mint::LEN];
+        Mint::pack(mint(Pubkey::new_unique()), &mut data).unwrap();
+        let info = decode_mint(&Pubkey::new_unique(), &account(spl_token::ID, data)).unwrap();
+        assert!(!info.is_token_2022());
+        assert_eq!(info.decimals, 6);
+        assert!(info.extensions.is_empty());
+    }
+
+    #[test]
+    fn decode_mint_rejects_other_owners() {
+        let mut data = vec![0; Mint::LEN];
+        Mint::pack(mint(Pubkey::new_unique()), &mut data).unwrap();
+        let other = account(Pubkey::new_unique(), data);
+        assert!(decode_mint(&Pubkey::new_unique(), &other).is_err());
+    }
+}
@@ -0,0 +1,447 @@
+use anyhow::{format_err, Result};
+use solana_sdk::{
+    address_lookup_table_account::AddressLookupTableAccount,
+    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
+    instruction::Instruction,
+    message::{v0, VersionedMessage},
+    pubkey::Pubkey,
+    signature::Signature,
+    system_instruction,
+    transaction::VersionedTransaction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+use spl_token::{
+    instruction::{close_account, sync_native},
+    native_mint, ID,
+};
+
+use crate::amm_math::{quote_swap, PoolState, Quote};
+use crate::instruction::swap;
+use crate::split::pool_swap_direction;
+
+// a single raydium swap with the ata setup stays well below this
+pub const DEFAULT_SWAP_COMPUTE_UNIT_LIMIT: u32 = 150_000;
+
+#[derive(Clone, Debug)]
+pub struct SwapTxConfig {
+    pub compute_unit_limit: u32,
+    /// micro lamports per compute unit, 0 leaves the price instruction out
+    pub compute_unit_price: u64,
+    /// wrap sol into the wsol ata before the swap and close it afterwards.
+    /// closing also unwraps any wsol the user already held there
+    pub wrap_unwrap_sol: bool,
+}
+
+impl Default for SwapTxConfig {
+    fn default() -> Self {
+        SwapTxConfig {
+            compute_unit_limit: DEFAULT_SWAP_COMPUTE_UNIT_LIMIT,
+            compute_unit_price: 0,
+            wrap_unwrap_sol: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UnsignedSwapTx {
+    /// signatures are placeholders until the user signs
+    pub transaction: VersionedTransaction,
+    pub quote: Quote,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+}
+
+fn output_mint(pool: &PoolState, input_mint: &Pubkey) -> Pubkey {
+    if *input_mint == pool.pool_amm_keys.amm_coin_mint {
+        pool.pool_amm_keys.amm_pc_mint
+    } else {
+        pool.pool_amm_keys.amm_coin_mint
+    }
+}
+
+// swap base in of amount_in, with the min out taken from the returned quote
+pub fn build_swap_instructions(
+    amm_program: &Pubkey,
+    pool: &PoolState,
+    user_owner: &Pubkey,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    slippage_bps: u64,
+    config: &SwapTxConfig,
+) -> Result<(Vec<Instruction>, Quote)> {
+    let swap_direction = pool_swap_direction(pool, input_mint)?;
+    let quote = quote_swap(&pool.pool, swap_direction, amount_in, true, slippage_bps)?;
+    let instructions = build_swap_instructions_with_min_out(
+        amm_program,
+        pool,
+        user_owner,
+        input_mint,
+        amount_in,
+        quote.other_amount_threshold,
+        config,
+    )?;
+    Ok((instructions, quote))
+}
+
+// swap base in of amount_in that fails below min_amount_out
+pub fn build_swap_instructions_with_min_out(
+    amm_program: &Pubkey,
+    pool: &PoolState,
+    user_owner: &Pubkey,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    min_amount_out: u64,
+    config: &SwapTxConfig,
+) -> Result<Vec<Instruction>> {
+    pool_swap_direction(pool, input_mint)?;
+    let output_mint = output_mint(pool, input_mint);
+    // spl token atas, PoolState is never loaded for token-2022 vaults
+    let user_source = get_associated_token_address(user_owner, input_mint);
+    let user_destination = get_associated_token_address(user_owner, &output_mint);
+
+    let mut instructions: Vec<Instruction> =
+        vec![ComputeBudgetInstruction::set_compute_unit_limit(
+            config.compute_unit_limit,
+        )];
+    if config.compute_unit_price > 0 {
+        instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+            config.compute_unit_price,
+        ));
+    }
+    instructions.push(create_associated_token_account_idempotent(
+        user_owner, user_owner, input_mint, &ID,
+    ));
+    instructions.push(create_associated_token_account_idempotent(
+        user_owner,
+        user_owner,
+        &output_mint,
+        &ID,
+    ));
+    let wsol_input = config.wrap_unwrap_sol && *input_mint == native_mint::id();
+    let wsol_output = config.wrap_unwrap_sol && output_mint == native_mint::id();
+    if wsol_input {
+        instructions.push(system_instruction::transfer(
+            user_owner,
+            &user_source,
+            amount_in,
+        ));
+        instructions.push(sync_native(&ID, &user_source)?);
+    }
+    instructions.push(swap(
+        amm_program,
+        &pool.pool_amm_keys,
+        &pool.pool_market_keys,
+        user_owner,
+        &user_source,
+        &user_destination,
+        amount_in,
+        min_amount_out,
+        true,
+    )?);
+    if wsol_input || wsol_output {
+        let wsol_account = if wsol_input {
+            user_source
+        } else {
+            user_destination
+        };
+        instructions.push(close_account(
+            &ID,
+            &wsol_account,
+            user_owner,
+            user_owner,
+            &[],
+        )?);
+    }
+    Ok(instructions)
+}
+
+// v0 transaction paid by payer, with default signatures in every required slot.
+// accounts found in lookup_tables are loaded from them instead of listed inline
+pub fn build_unsigned_v0_transaction(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+) -> Result<VersionedTransaction> {
+    let message = v0::Message::try_compile(payer, instructions, lookup_tables, recent_blockhash)?;
+    Ok(VersionedTransaction {
+        signatures: vec![Signature::default(); message.header.num_required_signatures as usize],
+        message: VersionedMessage::V0(message),
+    })
+}
+
+pub fn build_unsigned_swap_transaction(
+    amm_program: &Pubkey,
+    pool: &PoolState,
+    user_owner: &Pubkey,
+    input_mint: &Pubkey,
+    amount_in: u64,
+    slippage_bps: u64,
+    config: &SwapTxConfig,
+    lookup_tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+) -> Result<UnsignedSwapTx> {
+    let (instructions, quote) = build_swap_instructions(
+        amm_program,
+        pool,
+        user_owner,
+        input_mint,
+        amount_in,
+        slippage_bps,
+        config,
+    )?;
+    Ok(UnsignedSwapTx {
+        transaction: build_unsigned_v0_transaction(
+            user_owner,
+            &instructions,
+            lookup_tables,
+            recent_blockhash,
+        )?,
+        quote,
+        input_mint: *input_mint,
+        output_mint: output_mint(pool, input_mint),
+    })
+}
+
+// base64 wire format, as wallet adapters expect it
+pub fn serialize_transaction(transaction: &VersionedTransaction) -> Result<String> {
+    let serialized =
+        bincode::serialize(transaction).map_err(|e| format_err!("serialize transaction: {}", e))?;
+    Ok(base64::encode(serialized))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::pool_state;
+    use arrayref::array_ref;
+
+    fn config(compute_unit_price: u64) -> SwapTxConfig {
+        SwapTxConfig {
+            compute_unit_limit: 200_000,
+            compute_unit_price,
+            wrap_unwrap_sol: true,
+        }
+    }
+
+    // (amount_in, min_amount_out) of a swap base in instruction
+    fn swap_amounts(instruction: &Instruction) -> (u64, u64) {
+        let data = &instruction.data;
+        (
+            u64::from_le_bytes(*array_ref![data, 1, 8]),
+            u64::from_le_bytes(*array_ref![data, 9, 8]),
+        )
+    }
+
+    #[test]
+    fn swap_instructions_budget_then_atas_then_swap() {
+        let (coin, pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let pool = pool_state(coin, pc, 1_000_000, 2_000_000);
+        let (amm_program, user) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (instructions, quote) =
+            build_swap_instructions(&amm_program, &pool, &user, &coin, 10_000, 100, &config(7))
+                .unwrap();
+        assert_eq!(instructions.len(), 5);
+        assert_eq!(
+            instructions[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(200_000)
+        );
+        assert_eq!(
+            instructions[1],
+            ComputeBudgetInstruction::set_compute_unit_price(7)
+        );
+        for (instruction, mint) in instructions[2..4].iter().zip([coin, pc].iter()) {
+            assert_eq!(instruction.program_id, spl_associated_token_account::id());
+            assert_eq!(
+                instruction.accounts[1].pubkey,
+                get_associated_token_address(&user, mint)
+            );
+        }
+        let swap = &instructions[4];
+        assert_eq!(swap.program_id, amm_program);
+        assert_eq!(swap.accounts[1].pubkey, pool.pool_amm_keys.amm_pool);
+        assert_eq!(swap_amounts(swap), (10_000, quote.other_amount_threshold));
+    }
+
+    #[test]
+    fn swap_instructions_leave_out_a_zero_price() {
+        let (coin, pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let pool = pool_state(coin, pc, 1_000_000, 2_000_000);
+        let instructions = build_swap_instructions_with_min_out(
+            &Pubkey::new_unique(),
+            &pool,
+            &Pubkey::new_unique(),
+            &pc,
+            10_000,
+            42,
+            &config(0),
+        )
+        .unwrap();
+        assert_eq!(instructions.len(), 4);
+        assert_eq!(
+            instructions[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(200_000)
+        );
+        assert_eq!(
+            instructions[1].program_id,
+            spl_associated_token_account::id()
+        );
+        assert_eq!(swap_amounts(&instructions[3]), (10_000, 42));
+    }
+
+    #[test]
+    fn swap_instructions_wrap_sol_input_and_close_it() {
+        let (sol, pc) = (native_mint::id(), Pubkey::new_unique());
+        let pool = pool_state(sol, pc, 1_000_000, 2_000_000);
+        let user = Pubkey::new_unique();
+        let wsol_account = get_associated_token_address(&user, &sol);
+        let instructions = build_swap_instructions_with_min_out(
+            &Pubkey::new_unique(),
+            &pool,
+            &user,
+            &sol,
+            10_000,
+            1,
+            &config(0),
+        )
+        .unwrap();
+        assert_eq!(instructions.len(), 7);
+        assert_eq!(
+            instructions[3],
+            system_instruction::transfer(&user, &wsol_account, 10_000)
+        );
+        assert_eq!(instructions[4], sync_native(&ID, &wsol_account).unwrap());
+        assert_eq!(swap_amounts(&instructions[5]), (10_000, 1));
+        assert_eq!(
+            instructions[6],
+            close_account(&ID, &wsol_account, &user, &user, &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn swap_instructions_unwrap_sol_output() {
+        let (coin, sol) = (Pubkey::new_unique(), native_mint::id());
+        let pool = pool_state(coin, sol, 1_000_000, 2_000_000);
+        let user = Pubkey::new_unique();
+        let instructions = build_swap_instructions_with_min_out(
+            &Pubkey::new_unique(),
+            &pool,
+            &user,
+            &coin,
+            10_000,
+            1,
+            &config(0),
+        )
+        .unwrap();
+        // no transfer or sync, the swap is followed by the close
+        assert_eq!(instructions.len(), 5);
+        assert_eq!(swap_amounts(&instructions[3]), (10_000, 1));
+        assert_eq!(
+            instructions[4],
+            close_account(
+                &ID,
+                &get_associated_token_address(&user, &sol),
+                &user,
+                &user,
+                &[]
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn swap_instructions_keep_wsol_without_wrap_unwrap() {
+        let (sol, pc) = (native_mint::id(), Pubkey::new_unique());
+        let pool = pool_state(sol, pc, 1_000_000, 2_000_000);
+        let config = SwapTxConfig {
+            wrap_unwrap_sol: false,
+            ..config(0)
+        };
+        let instructions = build_swap_instructions_with_min_out(
+            &Pubkey::new_unique(),
+            &pool,
+            &Pubkey::new_unique(),
+            &sol,
+            10_000,
+            1,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(instructions.len(), 4);
+    }
+
+    #[test]
+    fn swap_instructions_reject_a_mint_the_pool_does_not_trade() {
+        let pool = pool_state(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 2_000);
+        assert!(build_swap_instructions(
+            &Pubkey::new_unique(),
+            &pool,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            10,
+            100,
+            &config(0),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn unsigned_transaction_loads_pool_accounts_from_the_lookup_table() {
+        let (coin, pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let pool = pool_state(coin, pc, 1_000_000, 2_000_000);
+        let keys = &pool.pool_amm_keys;
+        let market = &pool.pool_market_keys;
+        let (amm_program, user) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: vec![
+                keys.amm_pool,
+                keys.amm_authority,
+                keys.amm_open_order,
+                keys.amm_coin_vault,
+                keys.amm_pc_vault,
+                keys.market_program,
+                keys.market,
+                *market.bids,
+                *market.asks,
+                *market.event_q,
+                *market.coin_vault,
+                *market.pc_vault,
+                *market.vault_signer_key,
+            ],
+        };
+        let unsigned = build_unsigned_swap_transaction(
+            &amm_program,
+            &pool,
+            &user,
+            &coin,
+            10_000,
+            100,
+            &config(0),
+            &[table.clone()],
+            Hash::new_unique(),
+        )
+        .unwrap();
+        assert_eq!(unsigned.input_mint, coin);
+        assert_eq!(unsigned.output_mint, pc);
+        assert_eq!(unsigned.transaction.signatures, vec![Signature::default()]);
+        let message = match &unsigned.transaction.message {
+            VersionedMessage::V0(message) => message,
+            _ => panic!("not a v0 message"),
+        };
+        assert_eq!(message.account_keys[0], user);
+        assert_eq!(message.address_table_lookups.len(), 1);
+        let lookup = &message.address_table_lookups[0];
+        assert_eq!(lookup.account_key, table.key);
+        assert_eq!(
+            lookup.writable_indexes.len() + lookup.readonly_indexes.len(),
+            table.addresses.len()
+        );
+        for address in table.addresses.iter() {
+            assert!(!message.account_keys.contains(address));
+        }
+    }
+}